@@ -1,4 +1,10 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/devzat.proto")?;
+    let mut builder = tonic_build::configure();
+
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        builder = builder.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+
+    builder.compile(&["proto/devzat.proto"], &["proto"])?;
     Ok(())
 }
@@ -0,0 +1,322 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use crate::plugin::CmdInvocation;
+
+/// Errors returned while parsing the arguments of a [CmdInvocation].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArgError {
+    /// A `'` or `"` quote was opened but never closed.
+    UnterminatedQuote(char),
+    /// A `--name` that isn't a flag or option of the [ArgSpec].
+    UnknownFlag(String),
+    /// An option of the [ArgSpec] was last, with no value after it.
+    MissingValue(String),
+    /// A flag of the [ArgSpec] was given a value with `--flag=value`.
+    UnexpectedValue(String),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote(quote) => write!(f, "unterminated {} quote", quote),
+            Self::UnknownFlag(name) => write!(f, "unknown flag --{}", name),
+            Self::MissingValue(name) => write!(f, "missing value for --{}", name),
+            Self::UnexpectedValue(name) => write!(f, "--{} doesn't take a value", name),
+        }
+    }
+}
+
+impl Error for ArgError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArgKind {
+    Flag,
+    Option,
+}
+
+/// Flags and options accepted by a command, used by [CmdInvocation::parse].
+///
+/// Flags and options are written with their name after `--`, like `--admin`, and options take
+/// a value either as the next word or after an `=`, like `--name Ada` or `--name=Ada`.
+/// Everything else is positional, as well as every word after a lone `--`.
+///
+/// # Examples
+///
+/// ```
+/// # use devzat_rs::{ArgSpec, CmdInvocation};
+/// let spec = ArgSpec::new().option("name").flag("admin");
+/// let event = CmdInvocation {
+///     args: String::from("--name Ada --admin extra"),
+///     ..Default::default()
+/// };
+///
+/// let args = event.parse(&spec).unwrap();
+///
+/// assert_eq!(args.option("name"), Some("Ada"));
+/// assert!(args.flag("admin"));
+/// assert_eq!(args.positionals(), ["extra"]);
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct ArgSpec {
+    args: HashMap<String, ArgKind>,
+}
+
+impl ArgSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts `--name`, without a value.
+    pub fn flag<S: Into<String>>(mut self, name: S) -> Self {
+        self.args.insert(name.into(), ArgKind::Flag);
+        self
+    }
+
+    /// Accepts `--name value`. When given several times, the last value is kept.
+    pub fn option<S: Into<String>>(mut self, name: S) -> Self {
+        self.args.insert(name.into(), ArgKind::Option);
+        self
+    }
+}
+
+/// Number of arguments a command accepts, checked by
+/// [Client::register_cmd_checked](crate::Client::register_cmd_checked).
+///
+/// Arguments are counted once split like [CmdInvocation::parsed_args] does.
+///
+/// # Examples
+///
+/// ```
+/// # use devzat_rs::Arity;
+/// assert!(Arity::Exact(2).accepts(2));
+/// assert!(!Arity::Exact(2).accepts(1));
+/// assert!(!Arity::Exact(2).accepts(3));
+///
+/// assert!(Arity::AtLeast(1).accepts(5));
+/// assert!(!Arity::AtLeast(1).accepts(0));
+///
+/// assert!(Arity::Range(1, 3).accepts(3));
+/// assert!(!Arity::Range(1, 3).accepts(0));
+/// assert!(!Arity::Range(1, 3).accepts(4));
+/// ```
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly this many arguments.
+    Exact(usize),
+    /// This many arguments or more.
+    AtLeast(usize),
+    /// Between the two numbers of arguments, both included.
+    Range(usize, usize),
+}
+
+impl Arity {
+    /// Whether `count` arguments are accepted.
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Self::Exact(n) => count == n,
+            Self::AtLeast(n) => count >= n,
+            Self::Range(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+/// Arguments of a [CmdInvocation] parsed with an [ArgSpec].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedArgs {
+    flags: HashSet<String>,
+    options: HashMap<String, String>,
+    positionals: Vec<String>,
+}
+
+impl ParsedArgs {
+    /// Whether the flag `name` was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// The value of the option `name`, if it was given.
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    /// Every other argument, in order.
+    pub fn positionals(&self) -> &[String] {
+        &self.positionals
+    }
+}
+
+impl CmdInvocation {
+    /// Splits `args` into words the way a shell would: words are separated by whitespace,
+    /// `'single'` and `"double"` quotes group words together, and a backslash escapes the next
+    /// character. Inside double quotes a backslash only escapes `"` and `\`, and nothing is
+    /// escaped inside single quotes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::{ArgError, CmdInvocation};
+    /// let event = CmdInvocation {
+    ///     args: String::from(r#"  greet "Ada Lovelace" it\'s 'me'  "#),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(
+    ///     event.parsed_args(),
+    ///     Ok(vec![
+    ///         String::from("greet"),
+    ///         String::from("Ada Lovelace"),
+    ///         String::from("it's"),
+    ///         String::from("me"),
+    ///     ])
+    /// );
+    ///
+    /// let event = CmdInvocation {
+    ///     args: String::from(r#"greet "Ada"#),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(event.parsed_args(), Err(ArgError::UnterminatedQuote('"')));
+    /// ```
+    ///
+    pub fn parsed_args(&self) -> Result<Vec<String>, ArgError> {
+        let mut args = Vec::new();
+        let mut chars = self.args.chars();
+
+        // Tracked separately from `arg.is_empty()` so that `""` yields an empty argument.
+        let mut arg = String::new();
+        let mut in_arg = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => {
+                    in_arg = true;
+
+                    loop {
+                        match chars.next() {
+                            Some('\'') => break,
+                            Some(c) => arg.push(c),
+                            None => return Err(ArgError::UnterminatedQuote('\'')),
+                        }
+                    }
+                }
+                '"' => {
+                    in_arg = true;
+
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c @ ('"' | '\\')) => arg.push(c),
+                                Some(c) => {
+                                    arg.push('\\');
+                                    arg.push(c);
+                                }
+                                None => return Err(ArgError::UnterminatedQuote('"')),
+                            },
+                            Some(c) => arg.push(c),
+                            None => return Err(ArgError::UnterminatedQuote('"')),
+                        }
+                    }
+                }
+                '\\' => {
+                    in_arg = true;
+                    // A trailing backslash has nothing to escape, so it's kept as is.
+                    arg.push(chars.next().unwrap_or('\\'));
+                }
+                c if c.is_whitespace() => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                c => {
+                    in_arg = true;
+                    arg.push(c);
+                }
+            }
+        }
+
+        if in_arg {
+            args.push(arg);
+        }
+
+        Ok(args)
+    }
+
+    /// Splits `args` like [parsed_args](Self::parsed_args) does, then sorts the words into
+    /// the flags, options and positional arguments described by `spec`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::{ArgError, ArgSpec, CmdInvocation};
+    /// let spec = ArgSpec::new().option("name").flag("admin");
+    /// let event = |args: &str| CmdInvocation {
+    ///     args: String::from(args),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let args = event("--name=Ada -- --admin").parse(&spec).unwrap();
+    /// assert_eq!(args.option("name"), Some("Ada"));
+    /// assert!(!args.flag("admin"));
+    /// assert_eq!(args.positionals(), ["--admin"]);
+    ///
+    /// assert_eq!(
+    ///     event("--nick Ada").parse(&spec),
+    ///     Err(ArgError::UnknownFlag(String::from("nick")))
+    /// );
+    /// assert_eq!(
+    ///     event("--admin --name").parse(&spec),
+    ///     Err(ArgError::MissingValue(String::from("name")))
+    /// );
+    /// ```
+    ///
+    pub fn parse(&self, spec: &ArgSpec) -> Result<ParsedArgs, ArgError> {
+        let mut parsed = ParsedArgs::default();
+        let mut words = self.parsed_args()?.into_iter();
+
+        while let Some(word) = words.next() {
+            let arg = match word.strip_prefix("--") {
+                Some("") => {
+                    parsed.positionals.extend(words);
+                    break;
+                }
+                Some(arg) => arg,
+                None => {
+                    parsed.positionals.push(word);
+                    continue;
+                }
+            };
+
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            match (spec.args.get(name), value) {
+                (None, _) => return Err(ArgError::UnknownFlag(name.to_owned())),
+                (Some(ArgKind::Flag), None) => {
+                    parsed.flags.insert(name.to_owned());
+                }
+                (Some(ArgKind::Flag), Some(_)) => {
+                    return Err(ArgError::UnexpectedValue(name.to_owned()))
+                }
+                (Some(ArgKind::Option), value) => {
+                    let value = value
+                        .or_else(|| words.next())
+                        .ok_or_else(|| ArgError::MissingValue(name.to_owned()))?;
+                    parsed.options.insert(name.to_owned(), value);
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
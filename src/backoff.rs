@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// Exponential backoff used when retrying after a transient failure.
+///
+/// The delay starts at `initial` and doubles after every failed attempt, up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max }
+    }
+
+    pub(crate) fn next(&self, delay: Duration) -> Duration {
+        (delay * 2).min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    /// Starts at half a second and caps at 30 seconds.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
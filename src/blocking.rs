@@ -0,0 +1,139 @@
+//! Synchronous wrapper around [Client], for scripts and tools that don't run a tokio runtime
+//! themselves. Requires the `blocking` feature.
+//!
+//! The [BlockingClient] runs every call on a runtime of its own, so it must not be used from
+//! within an async context: doing so panics, like
+//! [Runtime::block_on](tokio::runtime::Runtime::block_on) does.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use devzat_rs::blocking::BlockingClient;
+//!
+//! # fn main() -> devzat_rs::PluginResult {
+//! let client = BlockingClient::new(
+//!     "https://devzat.hackclub.com:5556",
+//!     "dvz.token@hello.world1234",
+//! )?;
+//!
+//! client.send_message("#main", None, "Hello!".into(), None)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    plugin::{CmdInvocation, Event, Listener, Message},
+    Client, ClientBuilder, ClientError, PluginResult, Room,
+};
+
+/// Blocking counterpart of [Client]. See the [module docs](self).
+pub struct BlockingClient {
+    client: Client,
+    runtime: Runtime,
+}
+
+impl BlockingClient {
+    /// Same as [Client::new].
+    ///
+    /// # Panics
+    ///
+    /// Panics when called from within an async context, or if the runtime can't be started.
+    ///
+    pub fn new<H, T>(host: H, token: T) -> Result<Self, ClientError>
+    where
+        H: Into<String>,
+        T: Into<String>,
+    {
+        Self::from_builder(Client::builder().host(host).token(token))
+    }
+
+    /// Connects with the options of `builder`, see [ClientBuilder::build].
+    ///
+    /// # Panics
+    ///
+    /// Panics when called from within an async context, or if the runtime can't be started.
+    ///
+    pub fn from_builder(builder: ClientBuilder) -> Result<Self, ClientError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the tokio runtime");
+        let client = runtime.block_on(builder.build())?;
+
+        Ok(Self { client, runtime })
+    }
+
+    /// See [Client::send_message].
+    pub fn send_message(
+        &self,
+        room: impl Into<Room>,
+        from: Option<String>,
+        msg: String,
+        ephemeral_to: Option<String>,
+    ) -> PluginResult {
+        self.runtime
+            .block_on(self.client.send_message(room, from, msg, ephemeral_to))
+    }
+
+    /// See [Client::send].
+    pub fn send(&self, msg: Message) -> PluginResult {
+        self.runtime.block_on(self.client.send(msg))
+    }
+
+    /// See [Client::send_messages].
+    pub fn send_messages(&self, msgs: Vec<Message>) -> PluginResult {
+        self.runtime.block_on(self.client.send_messages(msgs))
+    }
+
+    /// See [Client::send_ephemeral].
+    pub fn send_ephemeral(
+        &self,
+        room: impl Into<Room>,
+        to: String,
+        msg: String,
+        from: Option<String>,
+    ) -> PluginResult {
+        self.runtime
+            .block_on(self.client.send_ephemeral(room, to, msg, from))
+    }
+
+    /// See [Client::ping].
+    pub fn ping(&self) -> PluginResult {
+        self.runtime.block_on(self.client.ping())
+    }
+
+    /// Same as [Client::register_listener], with a synchronous callback. Blocks until the
+    /// listener stream ends.
+    pub fn register_listener<F>(&self, listener: Listener, mut callback: F) -> PluginResult
+    where
+        F: FnMut(Event) -> Option<String>,
+    {
+        self.runtime
+            .block_on(self.client.register_listener(listener, |event| {
+                let result = callback(event);
+                async move { result }
+            }))
+    }
+
+    /// Same as [Client::register_cmd], with a synchronous callback. Blocks until the command
+    /// stream ends.
+    pub fn register_cmd<S, F>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> String,
+    {
+        self.runtime
+            .block_on(self.client.register_cmd(name, info, args_info, |event| {
+                let result = callback(event);
+                async move { result }
+            }))
+    }
+}
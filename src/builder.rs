@@ -0,0 +1,612 @@
+use futures_util::future::join_all;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+#[cfg(feature = "tls")]
+use tonic::transport::{Certificate, ClientTlsConfig};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::{
+    plugin::plugin_client::PluginClient, queue::RoomQueues, rate_limit::RateLimiter,
+    reply::ReplyDecorator, AuthInterceptor, Client, ClientError, Options, Room,
+    DEFAULT_AUTH_SCHEME,
+};
+
+/// `user-agent` sent unless changed with [ClientBuilder::user_agent].
+const DEFAULT_USER_AGENT: &str = concat!("devzat-rs/", env!("CARGO_PKG_VERSION"));
+
+/// Builder for a [Client] with optional connection settings.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::ClientBuilder;
+/// # use std::time::Duration;
+/// # async fn run() -> Result<(), devzat_rs::ClientError> {
+/// let client = ClientBuilder::new()
+///     .host("https://devzat.hackclub.com:5556")
+///     .token("dvz.token@hello.world1234")
+///     .connect_timeout(Duration::from_secs(5))
+///     .request_timeout(Duration::from_secs(10))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct ClientBuilder {
+    host: Option<String>,
+    hosts: Vec<String>,
+    token: Option<String>,
+    auth_scheme: Option<String>,
+    metadata: Vec<(String, String)>,
+    user_agent: Option<String>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    http2_keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    keep_alive_while_idle: Option<bool>,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+    bot_username: Option<String>,
+    rate_limit: Option<u32>,
+    callback_timeout: Option<Duration>,
+    reply_decorator: Option<ReplyDecorator>,
+    max_concurrent_callbacks: Option<usize>,
+    mention_invoker: bool,
+    default_room: Option<Room>,
+    lazy: bool,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+    #[cfg(feature = "gzip")]
+    gzip_threshold: Option<usize>,
+    #[cfg(feature = "tls")]
+    ca_certificate: Option<Certificate>,
+    #[cfg(feature = "tls")]
+    domain_name: Option<String>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder set up from the environment, for plugins configured like any other container:
+    ///
+    /// - `DEVZAT_HOST`: the [host](Self::host). A host without a scheme, like
+    ///   `devzat.hackclub.com:5556`, gets `https://` or `http://` depending on `DEVZAT_TLS`.
+    /// - `DEVZAT_TOKEN`: the [token](Self::token).
+    /// - `DEVZAT_TLS` (optional): `true` or `false`, whether a host without a scheme uses TLS.
+    ///   Defaults to `true`.
+    /// - `DEVZAT_TIMEOUT` (optional): the [request timeout](Self::request_timeout), in seconds.
+    ///
+    /// Fails with [ClientError::MissingEnv] listing every required variable that isn't set,
+    /// and with [ClientError::InvalidEnv] if an optional one can't be parsed. Other settings
+    /// can still be changed on the returned builder.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::ClientBuilder;
+    /// std::env::set_var("DEVZAT_HOST", "localhost:5556");
+    /// std::env::set_var("DEVZAT_TOKEN", "dvz.token@hello.world1234");
+    /// std::env::set_var("DEVZAT_TLS", "false");
+    /// std::env::set_var("DEVZAT_TIMEOUT", "2.5");
+    ///
+    /// let builder = ClientBuilder::from_env()?.bot_username("Rusty");
+    /// # Ok::<_, devzat_rs::ClientError>(())
+    /// ```
+    ///
+    pub fn from_env() -> Result<Self, ClientError> {
+        let var = |name: &str| std::env::var(name).ok();
+        let (host, token) = match (var("DEVZAT_HOST"), var("DEVZAT_TOKEN")) {
+            (Some(host), Some(token)) => (host, token),
+            (host, token) => {
+                let missing = [("DEVZAT_HOST", host), ("DEVZAT_TOKEN", token)]
+                    .into_iter()
+                    .filter(|(_, value)| value.is_none())
+                    .map(|(name, _)| String::from(name))
+                    .collect();
+
+                return Err(ClientError::MissingEnv(missing));
+            }
+        };
+
+        let tls = match var("DEVZAT_TLS").as_deref() {
+            None | Some("true" | "1") => true,
+            Some("false" | "0") => false,
+            Some(_) => return Err(ClientError::InvalidEnv(String::from("DEVZAT_TLS"))),
+        };
+
+        let host = match (host.contains("://"), tls) {
+            (true, _) => host,
+            (false, true) => format!("https://{}", host),
+            (false, false) => format!("http://{}", host),
+        };
+
+        let mut builder = Self::new().host(host).token(token);
+
+        if let Some(timeout) = var("DEVZAT_TIMEOUT") {
+            let timeout = timeout
+                .parse()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| ClientError::InvalidEnv(String::from("DEVZAT_TIMEOUT")))?;
+
+            builder = builder.request_timeout(timeout);
+        }
+
+        Ok(builder)
+    }
+
+    /// Address of the devzat plugin server, e.g. `https://devzat.hackclub.com:5556`.
+    ///
+    /// `http://` hosts, like a local `http://localhost:5556`, connect without TLS. `https://`
+    /// hosts need the `tls-rustls` or `tls-native-roots` feature, otherwise
+    /// [build](Self::build) fails with [ClientError::TlsNotEnabled].
+    pub fn host<S: Into<String>>(mut self, host: S) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Several addresses of the same devzat server, e.g. the plugin endpoints of a highly
+    /// available deployment, also used along with the [host](Self::host) if one is set. Can be
+    /// called several times to add more hosts.
+    ///
+    /// With more than one host, requests and streams are spread between the endpoints that are
+    /// up, picking the least loaded of two random endpoints for each, so one node going down
+    /// doesn't stop the plugin. [build](Self::build) connects to every host once and fails with
+    /// [ClientError::NoHostReachable] if none of them is up. After that, endpoints that go down
+    /// are skipped and reconnected in the background.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::ClientBuilder;
+    /// # async fn run() -> Result<(), devzat_rs::ClientError> {
+    /// let client = ClientBuilder::new()
+    ///     .hosts(["https://devzat-1.internal:5556", "https://devzat-2.internal:5556"])
+    ///     .token("dvz.token@hello.world1234")
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn hosts<I>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.hosts.extend(hosts.into_iter().map(Into::into));
+        self
+    }
+
+    /// Plugin token used to authenticate against the server. A token that isn't a valid header
+    /// value (e.g. one with a trailing newline) makes [build](Self::build) fail with
+    /// [ClientError::InvalidToken] before connecting.
+    pub fn token<S: Into<String>>(mut self, token: S) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Scheme put before the token in the `authorization` header, `Bearer` by default. An empty
+    /// scheme sends the token on its own, for devzat forks expecting the raw token.
+    pub fn auth_scheme<S: Into<String>>(mut self, scheme: S) -> Self {
+        self.auth_scheme = Some(scheme.into());
+        self
+    }
+
+    /// Sends a `name` header with `value` in every request, e.g. for a gateway in front of
+    /// devzat. Can be called several times to send several headers. [build](Self::build) fails
+    /// with [ClientError::InvalidMetadata] if the name or value isn't a valid ASCII header.
+    pub fn metadata<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.metadata.push((name.into(), value.into()));
+        self
+    }
+
+    /// Value of the `user-agent` header, identifying the plugin in the logs of the server.
+    /// Defaults to `devzat-rs/<version>`. tonic appends its own version, e.g.
+    /// `my-bot/1.0 tonic/0.7.2`.
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Maximum time to wait while establishing the connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum time to wait for each request to complete.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Interval between HTTP/2 pings sent to keep the connection alive. No pings are sent by
+    /// default; something like 30 seconds keeps listener streams alive behind proxies and NATs
+    /// that drop idle connections.
+    pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.http2_keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// How long to wait for a keepalive ping to be acknowledged before closing the connection.
+    /// Defaults to 20 seconds and only applies when
+    /// [http2_keep_alive_interval](Self::http2_keep_alive_interval) is set.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Whether to keep sending pings while there are no open streams. Defaults to `false`.
+    pub fn keep_alive_while_idle(mut self, enabled: bool) -> Self {
+        self.keep_alive_while_idle = Some(enabled);
+        self
+    }
+
+    /// Largest event or command invocation, in bytes, the client accepts from the server.
+    /// Bigger messages end the stream with [ClientError::MessageTooLarge]. Unlimited by
+    /// default.
+    ///
+    /// The tonic version used here doesn't limit message sizes itself, so the limit is checked
+    /// by the client once a message has been received.
+    pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Largest message, in bytes, the client sends. Sending a bigger message fails with
+    /// [ClientError::MessageTooLarge] without contacting the server. Unlimited by default.
+    pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+
+    /// Username the plugin posts as. Listeners skip events sent from it, so a bot that echoes
+    /// messages doesn't end up reacting to its own output.
+    pub fn bot_username<S: Into<String>>(mut self, username: S) -> Self {
+        self.bot_username = Some(username.into());
+        self
+    }
+
+    /// Send at most `per_second` messages per second, shared by every clone of the client.
+    /// Once the limit is reached, sending waits for the next slot instead of failing. Bursts of
+    /// up to `per_second` messages are sent right away. `0` disables the limit.
+    pub fn rate_limit(mut self, per_second: u32) -> Self {
+        self.rate_limit = Some(per_second).filter(|&per_second| per_second > 0);
+        self
+    }
+
+    /// Longest a single callback may run. Past it, the callback is dropped and the client moves
+    /// on to the next event: a command replies to the invoker, privately, that it timed out,
+    /// while a middleware lets the message through unchanged. Unlimited by default.
+    pub fn callback_timeout(mut self, timeout: Duration) -> Self {
+        self.callback_timeout = Some(timeout);
+        self
+    }
+
+    /// Run at most `limit` callbacks of [register_cmd_concurrent](Client::register_cmd_concurrent)
+    /// at once, shared by every clone of the client. Once the limit is reached, each command
+    /// reads at most one more invocation from devzat, which waits until a callback completes,
+    /// so the memory used during a burst stays bounded. `0` disables the limit, which is the
+    /// default.
+    pub fn max_concurrent_callbacks(mut self, limit: usize) -> Self {
+        self.max_concurrent_callbacks = Some(limit).filter(|&limit| limit > 0);
+        self
+    }
+
+    /// Starts every command reply with a mention of the user who invoked the command, like
+    /// `@Ada Hello!`, so they're notified. Replies that already mention them are left as is,
+    /// as are ephemeral replies, which only the invoker sees anyway. Disabled by default.
+    ///
+    /// The mention is added before the [reply decorator](Self::reply_decorator) runs.
+    pub fn mention_invoker(mut self, enabled: bool) -> Self {
+        self.mention_invoker = enabled;
+        self
+    }
+
+    /// Room used by [Client::send_to_default], so that a bot living in a single room doesn't
+    /// have to repeat it. A name without the `#` gets one, see [Room].
+    pub fn default_room(mut self, room: impl Into<Room>) -> Self {
+        self.default_room = Some(room.into());
+        self
+    }
+
+    /// Don't connect in [build](Self::build), but on the first request instead, so a plugin
+    /// started alongside devzat, e.g. in the same container setup, doesn't fail when it comes
+    /// up first. Disabled by default.
+    ///
+    /// Connection errors, like an unreachable host, are then returned by the first request or
+    /// stream rather than by [build](Self::build). A [malformed host](ClientError::MalformedHost)
+    /// or an invalid token still fail right away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::ClientBuilder;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), devzat_rs::ClientError> {
+    /// // Nothing listens on this port, yet the client is built.
+    /// let client = ClientBuilder::new()
+    ///     .host("http://127.0.0.1:1")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .connect_lazy(true)
+    ///     .build()
+    ///     .await?;
+    ///
+    /// assert!(client
+    ///     .send_message("#main", None, "Hello!".into(), None)
+    ///     .await
+    ///     .is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn connect_lazy(mut self, enabled: bool) -> Self {
+        self.lazy = enabled;
+        self
+    }
+
+    /// Rewrites every command reply before it's sent, e.g. to prefix it with an emoji or add a
+    /// signature, instead of formatting the reply in each command. Empty replies still aren't
+    /// sent, rather than sending just the decoration.
+    ///
+    /// Only the replies returned by command callbacks are decorated, not the messages sent
+    /// with [Client::send_message] or the replies about a cooldown or a timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::ClientBuilder;
+    /// # async fn run() -> Result<(), devzat_rs::ClientError> {
+    /// let client = ClientBuilder::new()
+    ///     .host("https://devzat.hackclub.com:5556")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .reply_decorator(|reply| format!("🦀 {}", reply))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn reply_decorator<F>(mut self, decorator: F) -> Self
+    where
+        F: Fn(String) -> String + Send + Sync + 'static,
+    {
+        self.reply_decorator = Some(ReplyDecorator(Arc::new(decorator)));
+        self
+    }
+
+    /// Compress requests and accept compressed responses with gzip. The devzat server must
+    /// also support the encoding, otherwise requests will fail.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    /// With [gzip](Self::gzip) enabled, only compress the messages sent with
+    /// [Client::send_message] and the like whose encoded size is over `bytes`, e.g. 512. Short
+    /// chat lines, the bulk of what a bot sends, then skip compressing, which costs more than
+    /// it saves on so few bytes. Compressed responses are accepted either way.
+    ///
+    /// tonic only sets the compression per client, not per request, so the client keeps a
+    /// second one compressing requests, on the same connection, for the larger messages. Other
+    /// requests, like registering commands and listeners, aren't compressed.
+    #[cfg(feature = "gzip")]
+    pub fn gzip_threshold(mut self, bytes: usize) -> Self {
+        self.gzip_threshold = Some(bytes);
+        self
+    }
+
+    /// Trust `cert` as the root certificate of the server, for self-hosted devzat instances
+    /// signed by a private CA.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Certificate, ClientBuilder};
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pem = std::fs::read("ca.pem")?;
+    ///
+    /// let client = ClientBuilder::new()
+    ///     .host("https://devzat.internal:5556")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .ca_certificate(Certificate::from_pem(pem))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    #[cfg(feature = "tls")]
+    pub fn ca_certificate(mut self, cert: Certificate) -> Self {
+        self.ca_certificate = Some(cert);
+        self
+    }
+
+    /// Name the server certificate is validated against, when it differs from the host.
+    #[cfg(feature = "tls")]
+    pub fn domain_name<S: Into<String>>(mut self, domain_name: S) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Builds the client, connecting to the [host](Self::host) or, with several
+    /// [hosts](Self::hosts), balancing requests between them.
+    ///
+    /// A host that isn't a valid URI fails with [ClientError::MalformedHost], and one that
+    /// can't be connected to with [ClientError::ConnectFailed], both naming the host. Without
+    /// any host, this fails with [ClientError::InvalidHost]. With
+    /// [connect_lazy](Self::connect_lazy), no connection is attempted here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::{ClientBuilder, ClientError};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let err = ClientBuilder::new()
+    ///     .host("http://127.0.0.1:1")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .build()
+    ///     .await
+    ///     .err()
+    ///     .unwrap();
+    ///
+    /// assert!(matches!(err, ClientError::ConnectFailed { .. }));
+    /// assert!(err.to_string().contains("http://127.0.0.1:1"));
+    ///
+    /// let err = ClientBuilder::new()
+    ///     .host("http://devzat host")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .build()
+    ///     .await
+    ///     .err()
+    ///     .unwrap();
+    ///
+    /// assert!(matches!(err, ClientError::MalformedHost { .. }));
+    /// assert!(err.to_string().contains("http://devzat host"));
+    ///
+    /// let err = ClientBuilder::new()
+    ///     .token("dvz.token@hello.world1234")
+    ///     .build()
+    ///     .await
+    ///     .err()
+    ///     .unwrap();
+    ///
+    /// assert!(matches!(err, ClientError::InvalidHost));
+    /// # }
+    /// ```
+    ///
+    pub async fn build(self) -> Result<Client, ClientError> {
+        let mut hosts: Vec<_> = self.host.iter().chain(&self.hosts).cloned().collect();
+
+        if hosts.is_empty() {
+            return Err(ClientError::InvalidHost);
+        }
+
+        let token = self.token.clone().ok_or(ClientError::InvalidToken)?;
+        let scheme = self.auth_scheme.as_deref().unwrap_or(DEFAULT_AUTH_SCHEME);
+        let auth = AuthInterceptor::new(token, scheme)?.with_metadata(self.metadata.clone())?;
+
+        let channel = match hosts.len() {
+            1 if self.lazy => self.endpoint(hosts.remove(0))?.connect_lazy(),
+            1 => {
+                let host = hosts.remove(0);
+
+                self.endpoint(host.clone())?
+                    .connect()
+                    .await
+                    .map_err(|source| ClientError::ConnectFailed { host, source })?
+            }
+            _ => {
+                let endpoints = hosts
+                    .into_iter()
+                    .map(|host| self.endpoint(host))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                // The balanced channel connects lazily, so each endpoint is tried here to fail
+                // early when none of them is up, as with a single host.
+                if !self.lazy {
+                    let attempts = join_all(endpoints.iter().map(Endpoint::connect)).await;
+
+                    if !attempts.iter().any(Result::is_ok) {
+                        return Err(ClientError::NoHostReachable);
+                    }
+                }
+
+                Channel::balance_list(endpoints.into_iter())
+            }
+        };
+
+        let client = PluginClient::with_interceptor(channel, auth.clone());
+
+        #[cfg(feature = "gzip")]
+        let (client, compressed) = match (self.gzip, self.gzip_threshold) {
+            (true, Some(threshold)) => (
+                client.clone().accept_gzip(),
+                Some((threshold, client.send_gzip().accept_gzip())),
+            ),
+            (true, None) => (client.send_gzip().accept_gzip(), None),
+            (false, _) => (client, None),
+        };
+
+        let options = Options {
+            bot_username: self.bot_username,
+            rate_limiter: self.rate_limit.map(RateLimiter::new),
+            callback_timeout: self.callback_timeout,
+            reply_decorator: self.reply_decorator,
+            callback_permits: self
+                .max_concurrent_callbacks
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+            mention_invoker: self.mention_invoker,
+            default_room: self.default_room,
+            room_queues: RoomQueues::default(),
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        };
+
+        Ok(Client {
+            client,
+            #[cfg(feature = "gzip")]
+            compressed,
+            auth,
+            options,
+        })
+    }
+
+    /// Endpoint for `host`, with every connection setting applied.
+    fn endpoint(&self, host: String) -> Result<Endpoint, ClientError> {
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let mut endpoint = Channel::from_shared(host.clone())
+            .map_err(|source| ClientError::MalformedHost { host, source })?
+            .user_agent(user_agent)?;
+        let https = endpoint.uri().scheme_str() == Some("https");
+
+        #[cfg(not(feature = "tls"))]
+        if https {
+            return Err(ClientError::TlsNotEnabled);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+
+        if let Some(interval) = self.http2_keep_alive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval);
+        }
+
+        if let Some(timeout) = self.keep_alive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+
+        if let Some(enabled) = self.keep_alive_while_idle {
+            endpoint = endpoint.keep_alive_while_idle(enabled);
+        }
+
+        #[cfg(feature = "tls")]
+        if https || self.ca_certificate.is_some() || self.domain_name.is_some() {
+            let mut tls = ClientTlsConfig::new();
+
+            if let Some(cert) = &self.ca_certificate {
+                tls = tls.ca_certificate(cert.clone());
+            }
+
+            if let Some(domain_name) = &self.domain_name {
+                tls = tls.domain_name(domain_name);
+            }
+
+            endpoint = endpoint.tls_config(tls)?;
+        }
+
+        Ok(endpoint)
+    }
+}
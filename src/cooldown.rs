@@ -0,0 +1,42 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Last invocation time of each user of a single command.
+#[derive(Debug)]
+pub(crate) struct Cooldown {
+    duration: Duration,
+    last_used: HashMap<String, Instant>,
+}
+
+impl Cooldown {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            last_used: HashMap::new(),
+        }
+    }
+
+    /// Records an invocation by `user`, or returns how long they still have to wait if they
+    /// invoked the command less than `duration` ago.
+    pub(crate) fn check(&mut self, user: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        if let Some(remaining) = self
+            .last_used
+            .get(user)
+            .and_then(|last| (*last + self.duration).checked_duration_since(now))
+        {
+            return Err(remaining);
+        }
+
+        // Forget users whose cooldown is over, so the map doesn't grow with every user ever seen.
+        let duration = self.duration;
+        self.last_used
+            .retain(|_, last| now.duration_since(*last) < duration);
+        self.last_used.insert(user.to_owned(), now);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use futures_util::future::BoxFuture;
+
+use crate::{plugin::Message, Client, PluginResult, Room};
+
+/// Sending side of a [Client], for bots generic over where their messages go, e.g. to test
+/// their handlers against the `MockClient` of the `testing` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, DevzatClient, PluginResult};
+/// async fn welcome<C: DevzatClient>(client: &C, user: &str) -> PluginResult {
+///     client
+///         .send_message("#main".into(), None, format!("Welcome {}!", user), None)
+///         .await
+/// }
+///
+/// # async fn run(client: Client) -> PluginResult {
+/// welcome(&client, "Ada").await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+pub trait DevzatClient {
+    /// Sends `msg`, see [Client::send].
+    fn send(&self, msg: Message) -> BoxFuture<'_, PluginResult>;
+
+    /// Sends a message built from its fields, see [Client::send_message].
+    fn send_message(
+        &self,
+        room: Room,
+        from: Option<String>,
+        msg: String,
+        ephemeral_to: Option<String>,
+    ) -> BoxFuture<'_, PluginResult> {
+        self.send(Message {
+            room: room.into(),
+            from,
+            msg,
+            ephemeral_to,
+        })
+    }
+
+    /// Sends `msg` to each of `rooms` and returns the result of each room, see
+    /// [Client::broadcast].
+    fn broadcast(
+        &self,
+        rooms: Vec<Room>,
+        from: Option<String>,
+        msg: String,
+    ) -> BoxFuture<'_, Vec<PluginResult>>
+    where
+        Self: Sync,
+    {
+        Box::pin(async move {
+            let mut results = Vec::with_capacity(rooms.len());
+
+            for room in rooms {
+                let result = self.send_message(room, from.clone(), msg.clone(), None);
+                results.push(result.await);
+            }
+
+            results
+        })
+    }
+}
+
+impl DevzatClient for Client {
+    fn send(&self, msg: Message) -> BoxFuture<'_, PluginResult> {
+        Box::pin(Client::send(self, msg))
+    }
+}
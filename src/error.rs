@@ -0,0 +1,201 @@
+use std::{error::Error, fmt};
+use tonic::{codegen::http::uri::InvalidUri, transport, Code, Status};
+
+/// Errors returned by the [Client](crate::Client) and its builder.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClientError {
+    /// The connection to the devzat server failed or was lost.
+    Transport(transport::Error),
+    /// The server answered a request with an error status.
+    Status(Status),
+    /// The token is missing or can't be used as an `authorization` header.
+    InvalidToken,
+    /// The name or value of a [ClientBuilder::metadata](crate::ClientBuilder::metadata) entry
+    /// can't be used as a header.
+    InvalidMetadata(String),
+    /// No [host](crate::ClientBuilder::host) was given to the builder.
+    InvalidHost,
+    /// `host` isn't a valid URI.
+    MalformedHost { host: String, source: InvalidUri },
+    /// Connecting to `host` failed.
+    ConnectFailed {
+        host: String,
+        source: transport::Error,
+    },
+    /// Required environment variables of
+    /// [ClientBuilder::from_env](crate::ClientBuilder::from_env) aren't set.
+    MissingEnv(Vec<String>),
+    /// An environment variable read by
+    /// [ClientBuilder::from_env](crate::ClientBuilder::from_env) has an invalid value.
+    InvalidEnv(String),
+    /// None of the [hosts](crate::ClientBuilder::hosts) could be connected to.
+    NoHostReachable,
+    /// The name given to [Room::parse](crate::Room::parse) isn't a valid room.
+    InvalidRoom(String),
+    /// The regex of a listener doesn't compile.
+    InvalidRegex(regex::Error),
+    /// The stream used to talk back to the server was closed.
+    StreamClosed,
+    /// A message is bigger than the limit configured on the [ClientBuilder](crate::ClientBuilder).
+    MessageTooLarge { size: usize, limit: usize },
+    /// The host uses `https://` but the crate was built without the `tls-rustls` or
+    /// `tls-native-roots` feature.
+    TlsNotEnabled,
+    /// A listener callback returned a message although the listener isn't a middleware, so
+    /// devzat has nowhere to put it.
+    MiddlewareMisuse,
+    /// A command with this name is already running on the [CommandRouter](crate::CommandRouter).
+    CommandExists(String),
+    /// The [CommandRouter](crate::CommandRouter) of a [RouterHandle](crate::RouterHandle) isn't
+    /// running anymore.
+    RouterStopped,
+    /// [Client::send_to_default](crate::Client::send_to_default) was called without a
+    /// [default room](crate::ClientBuilder::default_room).
+    NoDefaultRoom,
+}
+
+impl ClientError {
+    /// The status the server answered with, for [ClientError::Status] errors.
+    pub fn status(&self) -> Option<&Status> {
+        match self {
+            Self::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// The value of the `key` metadata entry the server answered with, such as a
+    /// `retry-after` hint. Trailers are included, as tonic merges them into the status
+    /// metadata. `None` for other errors, missing entries and non-ASCII values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::ClientError;
+    /// # use tonic::{metadata::MetadataMap, Code, Status};
+    /// let mut metadata = MetadataMap::new();
+    /// metadata.insert("retry-after", "30".parse().unwrap());
+    ///
+    /// let err = ClientError::from(Status::with_metadata(Code::Unavailable, "busy", metadata));
+    ///
+    /// assert_eq!(err.metadata("retry-after"), Some("30"));
+    /// assert_eq!(err.status().map(Status::message), Some("busy"));
+    /// assert_eq!(ClientError::InvalidToken.metadata("retry-after"), None);
+    /// ```
+    ///
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.status()?.metadata().get(key)?.to_str().ok()
+    }
+
+    /// Whether the error is caused by the connection rather than by the request itself, so
+    /// trying again later may succeed.
+    ///
+    /// This covers transport errors, failing to connect, a closed stream, no host being
+    /// reachable, and the `Unavailable`, `Unknown`, `Internal`, `Cancelled`, `Aborted` and
+    /// `DeadlineExceeded` status codes, which is how a dropped connection shows up.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Transport(_)
+            | Self::ConnectFailed { .. }
+            | Self::StreamClosed
+            | Self::NoHostReachable => true,
+            Self::Status(status) => matches!(
+                status.code(),
+                Code::Unavailable
+                    | Code::Unknown
+                    | Code::Internal
+                    | Code::Cancelled
+                    | Code::Aborted
+                    | Code::DeadlineExceeded
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether [Client::send_message_retry](crate::Client::send_message_retry) sends the
+    /// message again after this error: transport errors and the `Unavailable` and
+    /// `DeadlineExceeded` status codes. Unlike the other transient errors, such as an `Unknown`
+    /// or `Aborted` status answered by devzat, these don't suggest devzat got the message, so
+    /// retrying is unlikely to send it twice.
+    pub(crate) fn is_retryable(&self) -> bool {
+        match self {
+            Self::Transport(_) => true,
+            // Statuses built by tonic from a transport failure carry it as their source, unlike
+            // the ones answered by devzat.
+            Self::Status(status) => {
+                Error::source(status).is_some()
+                    || matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "transport error: {}", err),
+            Self::Status(status) => write!(f, "request failed: {}", status),
+            Self::InvalidToken => f.write_str("invalid token"),
+            Self::InvalidMetadata(name) => write!(f, "invalid metadata {}", name),
+            Self::InvalidHost => f.write_str("no host configured"),
+            Self::MalformedHost { host, source } => write!(f, "invalid host {}: {}", host, source),
+            Self::ConnectFailed { host, source } => {
+                write!(f, "couldn't connect to {}: {}", host, source)
+            }
+            Self::MissingEnv(names) => {
+                write!(f, "missing environment variables: {}", names.join(", "))
+            }
+            Self::InvalidEnv(name) => write!(f, "invalid environment variable {}", name),
+            Self::NoHostReachable => f.write_str("none of the hosts could be connected to"),
+            Self::InvalidRoom(name) => write!(f, "invalid room {}", name),
+            Self::InvalidRegex(err) => write!(f, "invalid regex: {}", err),
+            Self::StreamClosed => f.write_str("stream closed"),
+            Self::MessageTooLarge { size, limit } => write!(
+                f,
+                "message of {} bytes exceeds the {} byte limit",
+                size, limit
+            ),
+            Self::TlsNotEnabled => {
+                f.write_str("https hosts require the `tls-rustls` or `tls-native-roots` feature")
+            }
+            Self::CommandExists(name) => write!(f, "command {} already exists", name),
+            Self::RouterStopped => f.write_str("command router stopped"),
+            Self::NoDefaultRoom => f.write_str("no default room configured"),
+            Self::MiddlewareMisuse => {
+                f.write_str("listener returned a message although it isn't a middleware")
+            }
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err),
+            Self::Status(status) => Some(status),
+            Self::InvalidRegex(err) => Some(err),
+            Self::MalformedHost { source, .. } => Some(source),
+            Self::ConnectFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<transport::Error> for ClientError {
+    fn from(err: transport::Error) -> Self {
+        Self::Transport(err)
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+impl From<regex::Error> for ClientError {
+    fn from(err: regex::Error) -> Self {
+        Self::InvalidRegex(err)
+    }
+}
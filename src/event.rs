@@ -0,0 +1,190 @@
+use regex::Regex;
+use std::fmt;
+
+use crate::{
+    format,
+    plugin::{CmdInvocation, Event},
+};
+
+impl Event {
+    /// Whether `msg` mentions `@name`. Use [mentions_ignore_case](Self::mentions_ignore_case)
+    /// to also match `@Name`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::Event;
+    /// let event = Event {
+    ///     msg: String::from("hi @bot!"),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert!(event.mentions("bot"));
+    /// assert!(!event.mentions("bo"));
+    /// assert!(!event.mentions("Bot"));
+    /// assert!(event.mentions_ignore_case("Bot"));
+    /// ```
+    ///
+    pub fn mentions(&self, name: &str) -> bool {
+        mentions(&self.msg, name)
+    }
+
+    /// Same as [mentions](Self::mentions), but ignoring case.
+    pub fn mentions_ignore_case(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+
+        all_mentions(&self.msg).any(|mention| mention.to_lowercase() == name)
+    }
+
+    /// Every user mentioned in `msg`, in order and without the `@`.
+    ///
+    /// A mention is an `@` followed by letters, digits, `_` or `-`, so punctuation right after
+    /// it isn't part of the name. An `@` within a word, like in an email address, isn't a
+    /// mention.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::Event;
+    /// let event = Event {
+    ///     msg: String::from("@a @b, mail me at c@d.org"),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(event.mentioned_users(), ["a", "b"]);
+    /// ```
+    ///
+    pub fn mentioned_users(&self) -> Vec<String> {
+        all_mentions(&self.msg).map(String::from).collect()
+    }
+
+    /// The groups captured by `regex` in `msg`, or `None` if it doesn't match. The first one
+    /// is the whole match, followed by each group in order, `None` for the ones that didn't
+    /// participate in the match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::Event;
+    /// # use regex::Regex;
+    /// let regex = Regex::new(r"#(\d+)(?: \((\w+)\))?").unwrap();
+    /// let event = Event {
+    ///     msg: String::from("see #42 please"),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(
+    ///     event.captures(&regex),
+    ///     Some(vec![Some(String::from("#42")), Some(String::from("42")), None])
+    /// );
+    /// assert_eq!(Event::default().captures(&regex), None);
+    /// ```
+    ///
+    pub fn captures(&self, regex: &Regex) -> Option<Vec<Option<String>>> {
+        let captures = regex.captures(&self.msg)?;
+
+        Some(
+            captures
+                .iter()
+                .map(|group| group.map(|group| String::from(group.as_str())))
+                .collect(),
+        )
+    }
+
+    /// `reply` prefixed with `msg` quoted, like `> original` on its own line, so the reply
+    /// shows which message it answers. devzat messages can't refer to another message, so
+    /// this is done in the text, see [format::quote](crate::format::quote).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::Event;
+    /// let event = Event {
+    ///     msg: String::from("what time is it?"),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(event.quote("Noon."), "> what time is it?\nNoon.");
+    /// ```
+    ///
+    pub fn quote(&self, reply: &str) -> String {
+        format::quote(&self.msg, reply)
+    }
+}
+
+/// Whether `msg` mentions `@name`, see [Event::mentions].
+pub(crate) fn mentions(msg: &str, name: &str) -> bool {
+    all_mentions(msg).any(|mention| mention == name)
+}
+
+fn all_mentions(msg: &str) -> impl Iterator<Item = &str> {
+    msg.match_indices('@').filter_map(move |(i, _)| {
+        if msg[..i].chars().next_back().is_some_and(is_name_char) {
+            return None;
+        }
+
+        let name = &msg[i + 1..];
+        let end = name.find(|c| !is_name_char(c)).unwrap_or(name.len());
+
+        (end > 0).then(|| &name[..end])
+    })
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+impl CmdInvocation {
+    /// Everything typed after the command name, verbatim, e.g. to hand it to another service
+    /// as is.
+    ///
+    /// devzat only sends the arguments of an invocation, not the command name nor the whole
+    /// line, so this is the same as `args`: whitespace and quotes are kept, unlike with
+    /// [parsed_args](Self::parsed_args). The name is the one the command was registered with.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::CmdInvocation;
+    /// let event = CmdInvocation {
+    ///     args: String::from("summarize  \"the last\" 10 messages "),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(event.raw(), "summarize  \"the last\" 10 messages ");
+    /// assert_eq!(event.raw(), event.args);
+    /// ```
+    ///
+    pub fn raw(&self) -> &str {
+        &self.args
+    }
+}
+
+/// Formats the event as `[room] from: msg`.
+///
+/// ```
+/// # use devzat_rs::Event;
+/// let event = Event {
+///     room: String::from("#main"),
+///     from: String::from("Ada"),
+///     msg: String::from("Hello!"),
+/// };
+///
+/// assert_eq!(event.to_string(), "[#main] Ada: Hello!");
+/// ```
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.room, self.from, self.msg)
+    }
+}
+
+/// Formats the invocation as `[room] from invoked with: args`.
+impl fmt::Display for CmdInvocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} invoked with: {}",
+            self.room, self.from, self.args
+        )
+    }
+}
@@ -0,0 +1,228 @@
+//! Helpers wrapping text in the ANSI escape codes devzat renders, for use in the `msg` of a
+//! [Message](crate::Message).
+//!
+//! Every helper resets all attributes at the end, so the styles don't nest: use [sgr] to combine
+//! several of them on the same text.
+//!
+//! [sanitize] does the opposite, removing the escape codes from text written by users.
+//!
+//! # Examples
+//!
+//! ```
+//! use devzat_rs::format;
+//!
+//! assert_eq!(format::red("alert"), "\x1b[31malert\x1b[0m");
+//! assert_eq!(format::bold("loud"), "\x1b[1mloud\x1b[0m");
+//! assert_eq!(format::rgb(255, 128, 0, "orange"), "\x1b[38;2;255;128;0morange\x1b[0m");
+//! assert_eq!(format::sgr("1;31", "both"), "\x1b[1;31mboth\x1b[0m");
+//! ```
+
+/// Wraps `text` in the SGR escape code with the given `params`, followed by a reset.
+pub fn sgr(params: &str, text: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", params, text)
+}
+
+/// Colors `text` with a 24-bit foreground color.
+pub fn rgb(r: u8, g: u8, b: u8, text: &str) -> String {
+    sgr(&format!("38;2;{};{};{}", r, g, b), text)
+}
+
+macro_rules! styles {
+    ($($(#[$doc:meta])* $name:ident => $params:literal,)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(text: &str) -> String {
+                sgr($params, text)
+            }
+        )*
+    };
+}
+
+styles! {
+    /// Makes `text` bold.
+    bold => "1",
+    /// Makes `text` italic.
+    italic => "3",
+    /// Underlines `text`.
+    underline => "4",
+    /// Colors `text` black.
+    black => "30",
+    /// Colors `text` red.
+    red => "31",
+    /// Colors `text` green.
+    green => "32",
+    /// Colors `text` yellow.
+    yellow => "33",
+    /// Colors `text` blue.
+    blue => "34",
+    /// Colors `text` magenta.
+    magenta => "35",
+    /// Colors `text` cyan.
+    cyan => "36",
+    /// Colors `text` white.
+    white => "37",
+}
+
+/// Splits `msg` into chunks of at most `max_len` characters, to send long output as several
+/// messages, e.g. with [Client::send_chunked](crate::Client::send_chunked).
+///
+/// Chunks end at the last whitespace that fits, which is dropped, so words aren't cut in half.
+/// A word longer than `max_len` on its own is split wherever the limit falls. A `max_len` of
+/// `0` doesn't split `msg`, and an empty `msg` gives no chunks.
+///
+/// Lengths are counted in characters rather than bytes, so multi-byte characters aren't cut
+/// either. Escape codes, such as the ones added by the other helpers, count like any other
+/// characters.
+///
+/// # Examples
+///
+/// ```
+/// use devzat_rs::format;
+///
+/// assert_eq!(
+///     format::chunk("the quick brown fox", 10),
+///     ["the quick", "brown fox"]
+/// );
+/// assert_eq!(format::chunk("a\nb c", 3), ["a\nb", "c"]);
+/// assert_eq!(format::chunk("abcdefgh ij", 3), ["abc", "def", "gh", "ij"]);
+/// assert_eq!(format::chunk("héhé ça", 4), ["héhé", "ça"]);
+/// assert_eq!(format::chunk("short", 0), ["short"]);
+/// assert!(format::chunk("", 10).is_empty());
+/// ```
+pub fn chunk(msg: &str, max_len: usize) -> Vec<String> {
+    if msg.is_empty() {
+        return Vec::new();
+    }
+
+    if max_len == 0 {
+        return vec![msg.to_owned()];
+    }
+
+    let mut chunks = Vec::new();
+    let mut rest = msg;
+
+    // `end` is the byte index of the first character past the limit.
+    while let Some((end, next)) = rest.char_indices().nth(max_len) {
+        let split = match next.is_whitespace() {
+            true => Some(end),
+            false => rest[..end].rfind(char::is_whitespace),
+        };
+
+        let (head, tail) = match split {
+            Some(split) if !rest[..split].trim_end().is_empty() => {
+                (rest[..split].trim_end(), rest[split..].trim_start())
+            }
+            _ => (&rest[..end], &rest[end..]),
+        };
+
+        // Only whitespace before the first split, which isn't worth a message.
+        if !head.trim().is_empty() {
+            chunks.push(head.to_owned());
+        }
+
+        rest = tail;
+    }
+
+    if !rest.trim().is_empty() {
+        chunks.push(rest.to_owned());
+    }
+
+    chunks
+}
+
+/// Prefixes `reply` with `original` quoted like in an email, one `> ` line per line of
+/// `original`, to show which message a reply is about. See [Event::quote](crate::Event::quote).
+///
+/// The devzat protocol has no way to link a message to the one it answers, so the context has
+/// to be part of the text itself.
+///
+/// # Examples
+///
+/// ```
+/// use devzat_rs::format;
+///
+/// assert_eq!(format::quote("ping", "pong"), "> ping\npong");
+/// assert_eq!(format::quote("a\nb", "c"), "> a\n> b\nc");
+/// assert_eq!(format::quote("", "c"), "c");
+/// ```
+pub fn quote(original: &str, reply: &str) -> String {
+    let mut quoted = String::new();
+
+    for line in original.lines() {
+        quoted.push_str("> ");
+        quoted.push_str(line);
+        quoted.push('\n');
+    }
+
+    quoted.push_str(reply);
+    quoted
+}
+
+/// Removes the control characters from `text`, newlines and tabs aside, so that user input can
+/// be echoed back without messing up the terminals of the other users. Escape sequences are
+/// removed whole, whether they start with `ESC` or with their single character C1 form: control
+/// sequences such as `\x1b[2J`, strings such as the `\x1b]0;title\x07` setting the window title
+/// up to their terminator, and the shorter ones such as `\x1bc`. The rest of the text is kept as
+/// is. A string that isn't terminated runs to the end of the text, as it would in a terminal.
+///
+/// # Examples
+///
+/// ```
+/// use devzat_rs::format;
+///
+/// assert_eq!(format::sanitize("hi\x1b[2J there"), "hi there");
+/// assert_eq!(format::sanitize("\x1b[1;31mred\x1b[0m"), "red");
+/// assert_eq!(format::sanitize("pass\x08\x08\x08\x08word"), "password");
+/// assert_eq!(format::sanitize("\x1b]0;title\x07ok"), "ok");
+/// assert_eq!(format::sanitize("\x1b]0;title\x1b\\ok"), "ok");
+/// assert_eq!(format::sanitize("\u{9d}0;title\u{9c}ok"), "ok");
+/// assert_eq!(format::sanitize("\x1bPq#0\x1b\\ok"), "ok");
+/// assert_eq!(format::sanitize("\x1b]0;title"), "");
+/// assert_eq!(format::sanitize("\u{9b}2J"), "");
+/// assert_eq!(format::sanitize("reset\x1bc"), "reset");
+/// assert_eq!(format::sanitize("café\nrésumé\t✓"), "café\nrésumé\t✓");
+/// ```
+pub fn sanitize(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        // Both forms of an introducer, `ESC` followed by a character from `@` to `_`, or the C1
+        // character 0x40 above it.
+        let introducer = match c {
+            '\x1b' => chars.next_if(|c| ('\x40'..='\x5f').contains(c)),
+            '\u{80}'..='\u{9f}' => char::from_u32(c as u32 - 0x40),
+            _ => None,
+        };
+
+        match introducer {
+            // Control sequence: parameter and intermediate bytes, up to the final byte.
+            Some('[') => {
+                while chars.next_if(|c| ('\x20'..='\x3f').contains(c)).is_some() {}
+                chars.next_if(|c| ('\x40'..='\x7e').contains(c));
+            }
+            // Operating system command, device control string and the other strings, up to BEL
+            // or the string terminator, either `ESC \` or its C1 form.
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07'
+                        || c == '\u{9c}'
+                        || (c == '\x1b' && chars.next_if_eq(&'\\').is_some())
+                    {
+                        break;
+                    }
+                }
+            }
+            Some(_) => {}
+            // Other escape sequences: intermediate bytes, up to the final byte.
+            None if c == '\x1b' => {
+                while chars.next_if(|c| ('\x20'..='\x2f').contains(c)).is_some() {}
+                chars.next_if(|c| ('\x30'..='\x7e').contains(c));
+            }
+            None if !c.is_control() || c == '\n' || c == '\t' => sanitized.push(c),
+            None => {}
+        }
+    }
+
+    sanitized
+}
@@ -1,69 +1,439 @@
-use futures_util::stream;
-use std::error::Error;
+// `tonic::Status` makes `ClientError` large, but boxing it would make the error awkward to match.
+#![allow(clippy::result_large_err)]
+
+use cooldown::Cooldown;
+use rate_limit::RateLimiter;
+use regex::{Regex, RegexSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    task::{JoinError, JoinSet},
+    time::{sleep_until, Instant},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
 use tonic::{
     codegen::InterceptedService,
-    metadata::{Ascii, MetadataValue},
+    metadata::{Ascii, MetadataKey, MetadataValue},
     service::Interceptor,
     transport::Channel,
-    Request, Status,
+    Code, Request, Status, Streaming,
 };
 
+#[cfg(all(feature = "tls-rustls", feature = "tls-native-roots"))]
+compile_error!("the `tls-rustls` and `tls-native-roots` features can't be enabled together");
+
+/// Forwards to `tracing::debug!` when the `tracing` feature is enabled.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/// Forwards to `tracing::warn!` when the `tracing` feature is enabled.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+    };
+}
+
+/// Increments a `metrics` counter when the `metrics` feature is enabled.
+macro_rules! count {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "metrics")]
+        metrics::increment_counter!($($arg)*);
+    };
+}
+
+mod args;
+mod backoff;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod cooldown;
+mod devzat_client;
+mod error;
+mod event;
+pub mod format;
+mod listener;
+mod message;
+mod queue;
+mod rate_limit;
+mod registration;
+mod reply;
+mod room;
+mod router;
+mod runner;
+mod shutdown;
+mod stream;
+mod supervisor;
+mod task;
+#[cfg(feature = "testing")]
+pub mod testing;
+
 mod plugin {
     tonic::include_proto!("plugin");
 }
 
-use plugin::{
-    listener_client_data::Data, plugin_client::PluginClient, CmdDef, CmdInvocation, Event,
-    ListenerClientData, Message, MiddlewareResponse,
-};
+use plugin::{listener_client_data::Data, CmdDef, ListenerClientData};
+
+pub use args::{ArgError, ArgSpec, Arity, ParsedArgs};
+pub use backoff::Backoff;
+pub use builder::ClientBuilder;
+pub use devzat_client::DevzatClient;
+pub use error::ClientError;
+pub use listener::{ListenerBuilder, Middleware, PlainListener};
+pub use message::{MessageAck, MessageBuilder};
+pub use plugin::{plugin_client::PluginClient, CmdInvocation, Event, Listener, Message};
+pub use registration::Registration;
+pub use reply::CmdReply;
+pub use room::Room;
+pub use router::{CommandRouter, Next, RouterHandle};
+pub use runner::PluginRunner;
+pub use shutdown::Shutdown;
+pub use stream::EventStream;
+pub use supervisor::Supervisor;
+#[cfg(feature = "tls")]
+pub use tonic::transport::Certificate;
+
+pub type PluginResult = Result<(), ClientError>;
+
+/// Recipient of the [Client::ping] message. Devzat usernames can't contain spaces, so no user
+/// ever receives it.
+const PING_USER: &str = "devzat-rs ping";
+
+/// Scheme of the `authorization` header, unless changed with [ClientBuilder::auth_scheme].
+const DEFAULT_AUTH_SCHEME: &str = "Bearer";
 
-pub use plugin::Listener;
+/// Reply sent by [Client::register_cmd_fallible] when the callback fails.
+const CMD_ERROR_REPLY: &str = "Something went wrong while running this command.";
 
-pub type PluginResult = Result<(), Box<dyn Error>>;
+/// Reply sent by [Client::register_cmd_restricted] to users who aren't allowed to run the
+/// command.
+const CMD_DENIED_REPLY: &str = "Permission denied.";
+
+/// Reply sent to the invoker when a command callback runs past the
+/// [callback timeout](ClientBuilder::callback_timeout).
+const CMD_TIMEOUT_REPLY: &str = "This command timed out.";
 
 /// Generic implemenation of a gRCP client for a devzat plugin.
 ///
 /// See example usage: <https://github.com/TommyPujol06/devzat-plugin-rs>
 ///
+/// Cloning a `Client` is cheap: every clone shares the same underlying connection, so a clone
+/// can be handed to each spawned task.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::Client;
+/// # async fn run(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+/// let greeter = client.clone();
+/// let greeting = tokio::spawn(async move {
+///     greeter
+///         .send_message("#main", None, "Hello!".into(), None)
+///         .await
+/// });
+///
+/// client
+///     .send_message("#main", None, "Hi again!".into(), None)
+///     .await?;
+///
+/// greeting.await??;
+/// # Ok(())
+/// # }
+/// ```
+///
 #[derive(Clone)]
 pub struct Client {
     client: PluginClient<InterceptedService<Channel, AuthInterceptor>>,
+    /// Client compressing requests, used instead for messages over the
+    /// [gzip threshold](ClientBuilder::gzip_threshold).
+    #[cfg(feature = "gzip")]
+    compressed: Option<(usize, GrpcClient)>,
+    auth: AuthInterceptor,
+    options: Options,
+}
+
+#[cfg(feature = "gzip")]
+type GrpcClient = PluginClient<InterceptedService<Channel, AuthInterceptor>>;
+
+/// Settings from the [ClientBuilder] that apply after connecting.
+#[derive(Clone, Debug, Default)]
+struct Options {
+    bot_username: Option<String>,
+    rate_limiter: Option<RateLimiter>,
+    callback_timeout: Option<Duration>,
+    reply_decorator: Option<reply::ReplyDecorator>,
+    callback_permits: Option<Arc<Semaphore>>,
+    mention_invoker: bool,
+    default_room: Option<Room>,
+    room_queues: queue::RoomQueues,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+}
+
+impl Options {
+    fn check_size(size: usize, limit: Option<usize>) -> PluginResult {
+        match limit {
+            Some(limit) if size > limit => Err(ClientError::MessageTooLarge { size, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Awaits a callback, or returns `None` if it runs for longer than the callback timeout.
+    async fn timed<T>(&self, callback: impl std::future::Future<Output = T>) -> Option<T> {
+        match self.callback_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, callback).await.ok(),
+            None => Some(callback.await),
+        }
+    }
+
+    /// Waits for one of the [max_concurrent_callbacks](ClientBuilder::max_concurrent_callbacks)
+    /// slots to free up, or returns `None` right away without a limit.
+    async fn callback_permit(&self) -> Option<OwnedSemaphorePermit> {
+        let permits = self.callback_permits.clone()?;
+
+        // Nothing closes the semaphore, so `ok()` never throws a permit away.
+        permits.acquire_owned().await.ok()
+    }
+
+    /// Mentions `invoker` and applies the reply decorator to the reply of their command, or
+    /// returns `None` if the reply is blank, in which case nothing is sent.
+    fn decorate(&self, mut reply: CmdReply, invoker: &str) -> Option<CmdReply> {
+        if reply.is_blank() {
+            return None;
+        }
+
+        // Ephemeral replies are only shown to the invoker, who doesn't need to be told it's
+        // for them.
+        if self.mention_invoker && !reply.ephemeral && !event::mentions(&reply.msg, invoker) {
+            reply.msg = format!("@{} {}", invoker, reply.msg);
+        }
+
+        if let Some(decorator) = &self.reply_decorator {
+            reply.msg = (decorator.0)(reply.msg);
+        }
+
+        Some(reply)
+    }
 }
 
+/// Interceptor adding the plugin token to every request, as used by [Client::inner_mut].
+///
+/// The headers are parsed once when the client is built. Each request then only clones them,
+/// which shares their bytes instead of copying them, so the per-request cost is inserting them
+/// into the request metadata.
+///
+/// Clones share the token behind a lock, so that [Client::set_token] applies to every one of
+/// them. Requests only read it, which doesn't make them wait on each other.
 #[derive(Clone)]
-struct AuthInterceptor {
-    token: MetadataValue<Ascii>,
+pub struct AuthInterceptor {
+    token: Arc<RwLock<MetadataValue<Ascii>>>,
+    scheme: String,
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
 }
 
 impl AuthInterceptor {
-    pub fn new(token: String) -> Self {
-        let token = format!("Bearer {}", token).parse().unwrap();
-        Self { token }
+    /// Sends `token` after `scheme`, or on its own if `scheme` is empty. Fails with
+    /// [ClientError::InvalidToken] if the result isn't a valid header value.
+    ///
+    /// Clients build their own, so this is only needed to use the interceptor on its own, e.g.
+    /// to measure it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use devzat_rs::AuthInterceptor;
+    /// use tonic::service::Interceptor;
+    ///
+    /// let mut auth = AuthInterceptor::new(String::from("dvz.token@hello.world1234"), "Bearer")?;
+    /// let request = auth.call(tonic::Request::new(())).unwrap();
+    ///
+    /// assert_eq!(
+    ///     request.metadata().get("authorization").unwrap(),
+    ///     "Bearer dvz.token@hello.world1234"
+    /// );
+    /// # Ok::<_, devzat_rs::ClientError>(())
+    /// ```
+    ///
+    pub fn new(token: String, scheme: &str) -> Result<Self, ClientError> {
+        let token = Self::header(token, scheme)?;
+
+        Ok(Self {
+            token: Arc::new(RwLock::new(token)),
+            scheme: String::from(scheme),
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Sends `token` instead of the current one from now on, after the same scheme.
+    pub(crate) fn set_token(&self, token: String) -> PluginResult {
+        let token = Self::header(token, &self.scheme)?;
+        *self.token.write().unwrap_or_else(|err| err.into_inner()) = token;
+
+        Ok(())
+    }
+
+    fn header(token: String, scheme: &str) -> Result<MetadataValue<Ascii>, ClientError> {
+        let token = match scheme.is_empty() {
+            true => token,
+            false => format!("{} {}", scheme, token),
+        };
+
+        token.parse().map_err(|_| ClientError::InvalidToken)
+    }
+
+    /// Also sends each `(name, value)` pair of `metadata` with every request.
+    pub(crate) fn with_metadata(
+        mut self,
+        metadata: Vec<(String, String)>,
+    ) -> Result<Self, ClientError> {
+        for (name, value) in metadata {
+            let err = || ClientError::InvalidMetadata(name.clone());
+            let key = MetadataKey::from_bytes(name.as_bytes()).map_err(|_| err())?;
+            let value = value.parse().map_err(|_| err())?;
+
+            self.metadata.push((key, value));
+        }
+
+        Ok(self)
     }
 }
 
 impl Interceptor for AuthInterceptor {
     fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
-        request
-            .metadata_mut()
-            .insert("authorization", self.token.clone());
+        let metadata = request.metadata_mut();
+
+        for (key, value) in &self.metadata {
+            metadata.insert(key.clone(), value.clone());
+        }
+
+        // Inserted last, so that it can't be overridden by the extra metadata. Metadata values
+        // are backed by shared bytes, so cloning the token only bumps a reference count: the
+        // header value is built once, in `new`, rather than on every request.
+        let token = self.token.read().unwrap_or_else(|err| err.into_inner());
+        metadata.insert("authorization", token.clone());
 
         Ok(request)
     }
 }
 
 impl Client {
-    pub async fn new<S: Into<String>>(host: S, token: S) -> Result<Self, Box<dyn Error>> {
-        let channel = Channel::from_shared(host.into())?.connect().await?;
-        let auth = AuthInterceptor::new(token.into());
-        let client = PluginClient::with_interceptor(channel, auth);
+    /// Connects to `host` with the default settings. Use [Client::builder] to tune the
+    /// connection.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run() -> Result<(), devzat_rs::ClientError> {
+    /// let token = std::env::var("DEVZAT_TOKEN").unwrap();
+    /// let client = Client::new("https://devzat.hackclub.com:5556", token).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn new<H, T>(host: H, token: T) -> Result<Self, ClientError>
+    where
+        H: Into<String>,
+        T: Into<String>,
+    {
+        Self::builder().host(host).token(token).build().await
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Creates a client on top of an existing [Channel], e.g. one shared with other services or
+    /// configured with a custom connector. Unlike [Client::new], this doesn't connect by itself
+    /// and no [ClientBuilder] options are applied.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let channel = tonic::transport::Channel::from_static("http://localhost:5556")
+    ///     .connect()
+    ///     .await?;
+    ///
+    /// let client = Client::from_channel(channel, "dvz.token@hello.world1234")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn from_channel<T: Into<String>>(channel: Channel, token: T) -> Result<Self, ClientError> {
+        let auth = AuthInterceptor::new(token.into(), DEFAULT_AUTH_SCHEME)?;
+
+        Ok(Self {
+            client: PluginClient::with_interceptor(channel, auth.clone()),
+            #[cfg(feature = "gzip")]
+            compressed: None,
+            auth,
+            options: Options::default(),
+        })
+    }
+
+    /// Returns the generated gRPC client, to call RPCs this crate doesn't wrap yet.
+    ///
+    /// This is a lower-level surface than the rest of `Client`: it follows the devzat protocol
+    /// and the tonic version in use, so it may change between releases. Calls made through it
+    /// also bypass the [ClientBuilder] options such as the rate limit and the message size
+    /// limits.
+    ///
+    pub fn inner_mut(&mut self) -> &mut PluginClient<InterceptedService<Channel, AuthInterceptor>> {
+        &mut self.client
+    }
+
+    /// Consumes the `Client` and returns the generated gRPC client. See [inner_mut](Self::inner_mut).
+    ///
+    pub fn into_inner(self) -> PluginClient<InterceptedService<Channel, AuthInterceptor>> {
+        self.client
+    }
 
-        Ok(Self { client })
+    /// Authenticates with `token` from now on, e.g. after it was rotated, without reconnecting.
+    /// The [auth scheme](ClientBuilder::auth_scheme) and the other headers stay the same.
+    ///
+    /// The token is shared by every clone of the client, which all switch to the new one.
+    /// Requests and streams already started keep the old one. A token that can't be used as a
+    /// header fails with [ClientError::InvalidToken] and leaves the current one in place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::{Client, ClientError};
+    /// # use tonic::transport::Channel;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), ClientError> {
+    /// let channel = Channel::from_static("http://localhost:5556").connect_lazy();
+    /// let mut client = Client::from_channel(channel, "dvz.token@hello.world1234")?;
+    ///
+    /// client.set_token(String::from("dvz.token@hello.world5678"))?;
+    ///
+    /// assert!(matches!(
+    ///     client.set_token(String::from("dvz.token\n")),
+    ///     Err(ClientError::InvalidToken)
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn set_token(&mut self, token: String) -> PluginResult {
+        self.auth.set_token(token)
     }
 
     /// # Arguments
     ///
-    /// `room` - Chatroom where to send the message. In devzat the default room is `#main`.
+    /// `room` - Chatroom where to send the message. In devzat the default room is `#main`. A
+    /// name without the `#`, like `main`, gets one, see [Room].
     ///
     /// `from` - This is the username the message will be sent from.
     ///
@@ -73,148 +443,2293 @@ impl Client {
     ///
     /// # Examples
     ///
-    /// ```
-    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run() -> devzat_rs::PluginResult {
     /// let client = Client::new(
     ///     "https://devzat.hackclub.com:5556",
     ///     "dvz.token@hello.world1234",
-    /// );
+    /// )
+    /// .await?;
     ///
     /// let fut = client
     ///     .send_message(
     ///         String::from("#main"),
-    ///         String::from("Rusty"),
+    ///         Some(String::from("Rusty")),
     ///         String::from("Hello World from Rust!"),
     ///         None,
     ///     );
     ///
     /// fut.await?;
+    /// # Ok(())
+    /// # }
     /// ```
     ///
-
     pub async fn send_message(
         &self,
-        room: String,
+        room: impl Into<Room>,
         from: Option<String>,
         msg: String,
         ephemeral_to: Option<String>,
     ) -> PluginResult {
-        let msg = Message {
-            room,
+        self.send(Message {
+            room: room.into().into(),
             from,
             msg,
             ephemeral_to,
-        };
-
-        let mut mut_self = self.clone();
+        })
+        .await
+    }
 
-        mut_self.client.send_message(Request::new(msg)).await?;
+    /// Sends `msg` to the [default room](ClientBuilder::default_room), for bots living in a
+    /// single room. Fails with [ClientError::NoDefaultRoom] without one.
+    ///
+    /// [send](Self::send) already takes a whole [Message], hence the longer name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::{ClientBuilder, ClientError};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), ClientError> {
+    /// let client = ClientBuilder::new()
+    ///     .host("http://localhost:5556")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .connect_lazy(true)
+    ///     .build()
+    ///     .await?;
+    ///
+    /// assert!(matches!(
+    ///     client.send_to_default("Hello!").await,
+    ///     Err(ClientError::NoDefaultRoom)
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn send_to_default<S: Into<String>>(&self, msg: S) -> PluginResult {
+        let room = self
+            .options
+            .default_room
+            .clone()
+            .ok_or(ClientError::NoDefaultRoom)?;
 
-        Ok(())
+        self.send_message(room, None, msg.into(), None).await
     }
 
-    /// # Arguments
+    /// Sends a [Message], usually built with [Message::to]. A room without its `#` gets one,
+    /// see [Room].
     ///
-    /// `listener` - [Listener] struct containing information about the listener.
-    ///
-    /// `callback` - Asynchronous function to be executed.
+    /// Messages to the same room are sent one after the other, in the order they're passed in,
+    /// even from tasks running concurrently, while messages to other rooms don't wait on them.
+    /// Once 32 messages are waiting for a room, sending another one to it waits for room.
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Message};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .send(Message::to("#main").text("hi").from("bot").build())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
     /// ```
-    /// let listener = Listener {
-    ///     middleware: None,
-    ///     once: None,
-    ///     regex: None,
-    /// };
     ///
-    /// client
-    ///     .register_listener(listener, |event| move async {
-    ///         eprintln!("room={}, from={}, msg={}", event.room, event.from, event.msg);
-    ///     }).await?;
+    pub async fn send(&self, msg: Message) -> PluginResult {
+        self.send_within(msg, None).await.map(|_| ())
+    }
+
+    /// Same as [send_message](Self::send_message), but fails instead of waiting for the server
+    /// once `deadline` has passed, e.g. for announcements that are pointless when late.
+    ///
+    /// The time spent waiting behind earlier messages to the same room counts towards the
+    /// deadline. The deadline is also sent to devzat, which may give up on the message as well.
+    /// Past it, this fails with a [ClientError::Status] whose code is `DeadlineExceeded`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, ClientError};
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) {
+    /// let result = client
+    ///     .send_message_with_deadline(
+    ///         "#main",
+    ///         None,
+    ///         "The vote closes now!".into(),
+    ///         None,
+    ///         Duration::from_millis(500),
+    ///     )
+    ///     .await;
+    ///
+    /// if let Err(ClientError::Status(status)) = result {
+    ///     eprintln!("announcement dropped: {}", status);
+    /// }
+    /// # }
     /// ```
     ///
-    pub async fn register_listener<F, Fut>(&self, listener: Listener, callback: F) -> PluginResult
-    where
-        F: FnOnce(Event) -> Fut + Copy,
-        Fut: std::future::Future<Output = Option<String>>,
-    {
-        let mut mut_self = self.clone();
+    pub async fn send_message_with_deadline(
+        &self,
+        room: impl Into<Room>,
+        from: Option<String>,
+        msg: String,
+        ephemeral_to: Option<String>,
+        deadline: Duration,
+    ) -> PluginResult {
+        let msg = Message {
+            room: room.into().into(),
+            from,
+            msg,
+            ephemeral_to,
+        };
 
-        let listener_data = stream::iter(vec![ListenerClientData {
-            data: Some(Data::Listener(listener.clone())),
-        }]);
+        self.send_within(msg, Some(deadline)).await.map(|_| ())
+    }
 
-        let mut event = mut_self
-            .client
-            .register_listener(listener_data)
-            .await?
-            .into_inner();
+    /// Sends `msg`, giving up after `deadline` if there's one.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(room = %msg.room, from = ?msg.from))
+    )]
+    async fn send_within(
+        &self,
+        mut msg: Message,
+        deadline: Option<Duration>,
+    ) -> Result<MessageAck, ClientError> {
+        debug!("sending message");
+
+        // Messages built by hand skip the conversions into a `Room`.
+        msg.room = Room::new(msg.room).into();
+
+        let size = prost::Message::encoded_len(&msg);
+        Options::check_size(size, self.options.max_encoding_message_size)?;
 
-        while let Some(event) = event.message().await? {
-            let result = callback(event).await;
+        // Only the generated client needs to be mutable: cloning it just bumps the channel's
+        // reference counts, unlike cloning the whole `Client` and its options.
+        let mut client = self.client.clone();
 
-            if !listener.middleware() && result.is_some() {
-                panic!("Function returned a value although it's not marked as a middleware.");
+        #[cfg(feature = "gzip")]
+        if let Some((threshold, compressed)) = &self.compressed {
+            if size > *threshold {
+                client = compressed.clone();
             }
+        }
+
+        let limiter = self.options.rate_limiter.clone();
+        let room = msg.room.clone();
+        let started = Instant::now();
+
+        // Messages to the same room go through its queue, so they're sent in the order they
+        // were passed in even when sent from several tasks at once.
+        let delivery = async move {
+            if let Some(limiter) = limiter {
+                limiter.acquire().await;
+            }
+
+            let mut request = Request::new(msg);
+
+            // The time spent in the queue counts towards the deadline.
+            if let Some(deadline) = deadline {
+                match deadline.checked_sub(started.elapsed()) {
+                    Some(left) => request.set_timeout(left),
+                    None => return Err(Status::cancelled("deadline exceeded")),
+                }
+            }
+
+            client.send_message(request).await
+        };
+
+        let queued = self.options.room_queues.run(&room, delivery);
 
-            // TBD: Send/Write this? How?
-            // https://github.com/Merlin04/devzat-node/blob/be29a311371b2d7c9814e5dc6cda3a955a8cf628/src/index.ts#L108
+        // The messages queued before this one count towards the deadline too, so waiting for
+        // them is bounded as well. Past the deadline, the message is dropped if it's still
+        // queued.
+        let result = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_sub(started.elapsed());
 
-            Data::Response(MiddlewareResponse { msg: result });
+                tokio::time::timeout(left, queued)
+                    .await
+                    .map_err(|_| Status::deadline_exceeded("deadline exceeded"))?
+            }
+            None => queued.await,
         }
+        .ok_or(ClientError::StreamClosed)?;
 
-        Ok(())
+        let response = match result {
+            // tonic cancels the request itself once the timeout elapses, which is reported the
+            // same way as any other cancellation.
+            Err(status)
+                if status.code() == Code::Cancelled
+                    && deadline.is_some_and(|deadline| started.elapsed() >= deadline) =>
+            {
+                return Err(Status::deadline_exceeded("deadline exceeded").into());
+            }
+            result => result?,
+        };
+
+        count!("devzat_messages_sent_total");
+
+        Ok(response.into_inner().into())
     }
 
-    /// # Arguments
+    /// Same as [send](Self::send), but returns what devzat answered, e.g. to log it along with
+    /// the message.
     ///
-    /// `name` - Command name.
+    /// devzat doesn't answer with anything yet, such as a message ID, so the [MessageAck] only
+    /// confirms the message was accepted. It will carry whatever later versions of the
+    /// protocol add.
     ///
-    /// `info` - Command information.
+    /// # Examples
     ///
-    /// `args_info` - Information about the command arguments.
+    /// ```no_run
+    /// # use devzat_rs::{Client, Message};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let ack = client
+    ///     .send_message_ack(Message::to("#main").text("Hello!").build())
+    ///     .await?;
+    ///
+    /// eprintln!("sent: {:?}", ack);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn send_message_ack(&self, msg: Message) -> Result<MessageAck, ClientError> {
+        self.send_within(msg, None).await
+    }
+
+    /// Sends several messages, one after the other.
     ///
-    /// `callback` - Asynchronous function that will be ran on command invocation.
+    /// Each message is sent once the previous one has been accepted, so they arrive in order.
+    /// Sending stops at the first error, which is returned, and the remaining messages aren't
+    /// sent.
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Message};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let help = ["Commands:", "  greet <name>", "  help"]
+    ///     .into_iter()
+    ///     .map(|line| Message::to("#main").text(line).build())
+    ///     .collect();
+    ///
+    /// client.send_messages(help).await?;
+    /// # Ok(())
+    /// # }
     /// ```
+    ///
+    pub async fn send_messages(&self, msgs: Vec<Message>) -> PluginResult {
+        for msg in msgs {
+            self.send(msg).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `msg` to `room` as several messages of at most `max_len` characters, split with
+    /// [format::chunk], for output that would be cut off as a single line.
+    ///
+    /// The chunks are sent in order like with [send_messages](Self::send_messages), stopping at
+    /// the first error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client, report: String) -> devzat_rs::PluginResult {
     /// client
-    ///     .register_cmd("greet", "Greet someone.", "<name>", |event| async move {
-    ///         format!("Hello {}!", event.args)
-    ///     }).await?;
+    ///     .send_chunked("#main", Some("Rusty".into()), &report, 200)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
     /// ```
     ///
+    pub async fn send_chunked(
+        &self,
+        room: impl Into<Room>,
+        from: Option<String>,
+        msg: &str,
+        max_len: usize,
+    ) -> PluginResult {
+        let room = String::from(room.into());
+        let msgs = format::chunk(msg, max_len)
+            .into_iter()
+            .map(|chunk| Message {
+                room: room.clone(),
+                from: from.clone(),
+                msg: chunk,
+                ephemeral_to: None,
+            })
+            .collect();
 
-    pub async fn register_cmd<S, F, Fut>(
+        self.send_messages(msgs).await
+    }
+
+    /// Sends `msg` to each of `rooms`, e.g. for an announcement in several rooms.
+    ///
+    /// Unlike [send_messages](Self::send_messages), a failure doesn't stop the other rooms from
+    /// getting the message: the result of each room is returned, in the order of `rooms`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) {
+    /// let rooms = vec!["#main".into(), "#general".into(), "#mods".into()];
+    /// let results = client
+    ///     .broadcast(rooms.clone(), None, "Maintenance in 10 minutes.".into())
+    ///     .await;
+    ///
+    /// for (room, result) in rooms.iter().zip(results) {
+    ///     if let Err(err) = result {
+    ///         eprintln!("couldn't announce in {}: {}", room, err);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    pub async fn broadcast(
         &self,
-        name: S,
-        info: S,
-        args_info: S,
-        callback: F,
-    ) -> PluginResult
-    where
-        S: Into<String>,
-        F: FnOnce(CmdInvocation) -> Fut + Copy,
-        Fut: std::future::Future<Output = String>,
-    {
-        let mut mut_self = self.clone();
+        rooms: Vec<Room>,
+        from: Option<String>,
+        msg: String,
+    ) -> Vec<PluginResult> {
+        DevzatClient::broadcast(self, rooms, from, msg).await
+    }
 
-        let cmd = CmdDef {
-            name: name.into(),
-            info: info.into(),
-            args_info: args_info.into(),
+    /// Checks that the server is reachable and accepts the token, e.g. before registering
+    /// anything or from a readiness probe.
+    ///
+    /// The devzat protocol has no dedicated health RPC, so this calls `SendMessage` with an
+    /// empty ephemeral message addressed to a username devzat can't hand out, which nobody
+    /// receives. Errors answered by devzat itself, such as the user not being found, still
+    /// prove the round-trip worked and aren't returned. Connection failures, `Unavailable` and
+    /// `DeadlineExceeded` statuses and a rejected token are.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client.ping().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn ping(&self) -> PluginResult {
+        let msg = Message {
+            room: String::from("#main"),
+            ephemeral_to: Some(String::from(PING_USER)),
+            ..Default::default()
         };
 
-        let mut event = mut_self.client.register_cmd(cmd).await?.into_inner();
+        let mut client = self.client.clone();
 
-        while let Some(event) = event.message().await? {
-            let room = event.room.clone();
-            let result = callback(event).await;
-            mut_self.send_message(room, None, result, None).await?;
+        match client.send_message(Request::new(msg)).await {
+            // Statuses built by tonic from a transport failure carry it as their source, unlike
+            // the ones answered by devzat.
+            Err(status)
+                if std::error::Error::source(&status).is_some()
+                    || matches!(
+                        status.code(),
+                        Code::Unauthenticated
+                            | Code::PermissionDenied
+                            | Code::Unavailable
+                            | Code::DeadlineExceeded
+                    ) =>
+            {
+                Err(status.into())
+            }
+            _ => Ok(()),
         }
+    }
 
-        Ok(())
+    /// Waits until the server is up and accepts the token, [pinging](Self::ping) it with a
+    /// growing delay between attempts, e.g. in the entrypoint of a plugin started along with
+    /// devzat and built with [connect_lazy](ClientBuilder::connect_lazy).
+    ///
+    /// Fails with a `DeadlineExceeded` status once `timeout` elapses, or right away if the
+    /// failure isn't [transient](ClientError::is_transient), like a rejected token.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::ClientBuilder;
+    /// # use std::time::Duration;
+    /// # use tonic::Code;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), devzat_rs::ClientError> {
+    /// let client = ClientBuilder::new()
+    ///     .host("http://127.0.0.1:1")
+    ///     .token("dvz.token@hello.world1234")
+    ///     .connect_lazy(true)
+    ///     .build()
+    ///     .await?;
+    ///
+    /// let err = client
+    ///     .wait_until_ready(Duration::from_millis(300))
+    ///     .await
+    ///     .unwrap_err();
+    ///
+    /// assert_eq!(err.status().map(|status| status.code()), Some(Code::DeadlineExceeded));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn wait_until_ready(&self, timeout: Duration) -> PluginResult {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(5));
+
+        let ready = async {
+            let mut delay = backoff.initial;
+
+            loop {
+                match self.ping().await {
+                    Err(err) if err.is_transient() => {
+                        debug!(error = %err, "server not ready yet");
+                        tokio::time::sleep(delay).await;
+                        delay = backoff.next(delay);
+                    }
+                    result => return result,
+                }
+            }
+        };
+
+        match tokio::time::timeout(timeout, ready).await {
+            Ok(result) => result,
+            Err(_) => Err(Status::deadline_exceeded("server not ready in time").into()),
+        }
+    }
+
+    /// Same as [send](Self::send), but failures to reach devzat, which are transport errors and
+    /// the `Unavailable` and `DeadlineExceeded` statuses, are retried up to `retries` times,
+    /// waiting `backoff` before the first retry and twice as long before each following one.
+    ///
+    /// Other errors are returned right away, even [transient](ClientError::is_transient) ones
+    /// like an `Unknown` or `Internal` status: devzat may have got the message already, which
+    /// retrying would send twice. So are errors with the message itself, like an
+    /// `InvalidArgument` status.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Message};
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let msg = Message::to("#main").text("Server restarting in 5 minutes!").build();
+    ///
+    /// client
+    ///     .send_message_retry(msg, 3, Duration::from_millis(200))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn send_message_retry(
+        &self,
+        msg: Message,
+        retries: u32,
+        backoff: Duration,
+    ) -> PluginResult {
+        let mut delay = backoff;
+
+        for _ in 0..retries {
+            match self.send(msg.clone()).await {
+                Err(err) if err.is_retryable() => {
+                    debug!(error = %err, "retrying message");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+
+        self.send(msg).await
+    }
+
+    /// Sends `msg` to `room` without a sender, for announcements that shouldn't look like they
+    /// come from the bot. Devzat shows such messages on their own, without the `name: ` prefix
+    /// of the messages sent by users.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .send_system_message(
+    ///         String::from("#main"),
+    ///         String::from("The server restarts in 5 minutes."),
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn send_system_message(&self, room: impl Into<Room>, msg: String) -> PluginResult {
+        self.send_message(room, None, msg, None).await
+    }
+
+    /// Sends `msg` to the room `event` was sent in.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # use futures_util::StreamExt;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let mut events = client.listen(Listener::builder().regex("^hi$").build()).await?;
+    ///
+    /// while let Some(event) = events.next().await {
+    ///     let event = event?;
+    ///     client.reply_to(&event, format!("Hi {}!", event.from)).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn reply_to(&self, event: &Event, msg: String) -> PluginResult {
+        self.send_message(event.room.clone(), None, msg, None).await
+    }
+
+    /// Sends `msg` to the room `event` was sent in, only visible to the user who sent it.
+    pub async fn reply_privately(&self, event: &Event, msg: String) -> PluginResult {
+        self.send_ephemeral(event.room.clone(), event.from.clone(), msg, None)
+            .await
+    }
+
+    /// Sends `msg` to the room the command was invoked in, e.g. for a second message from a
+    /// [register_cmd_with_client](Self::register_cmd_with_client) callback.
+    pub async fn reply_to_cmd(&self, invocation: &CmdInvocation, msg: String) -> PluginResult {
+        self.send_message(invocation.room.clone(), None, msg, None)
+            .await
+    }
+
+    /// Sends a message that is only visible to `to`, in the given room.
+    ///
+    /// # Arguments
+    ///
+    /// `room` - Chatroom where to send the message.
+    ///
+    /// `to` - The only user that will see the message.
+    ///
+    /// `msg` - This is the actual message that will be sent.
+    ///
+    /// `from` - This is the username the message will be sent from.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .send_ephemeral(
+    ///         String::from("#main"),
+    ///         String::from("Ada"),
+    ///         String::from("Only you can see this."),
+    ///         None,
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn send_ephemeral(
+        &self,
+        room: impl Into<Room>,
+        to: String,
+        msg: String,
+        from: Option<String>,
+    ) -> PluginResult {
+        self.send_message(room, from, msg, Some(to)).await
+    }
+
+    /// Registers a listener and returns the stream of its events, as an alternative to the
+    /// callbacks of [register_listener](Self::register_listener).
+    ///
+    /// The stream ends with `None` when devzat closes it cleanly, while a dropped connection
+    /// yields an error instead, for which [ClientError::is_transient] is `true`.
+    ///
+    /// An invalid `regex` fails with [ClientError::InvalidRegex] before anything is sent to
+    /// devzat. Middleware listeners must answer every event with [EventStream::respond].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # use futures_util::StreamExt;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let mut events = client
+    ///     .listen(Listener::builder().middleware(true).build())
+    ///     .await?;
+    ///
+    /// while let Some(event) = events.next().await {
+    ///     let event = event?;
+    ///     events.respond(Some(event.msg.to_uppercase())).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn listen(&self, listener: Listener) -> Result<EventStream, ClientError> {
+        // devzat silently ignores listeners with an invalid regex, so catch it here instead.
+        if let Some(regex) = &listener.regex {
+            Regex::new(regex)?;
+        }
+
+        let mut client = self.client.clone();
+
+        // The listener stream stays open for as long as we hold `tx`, which is how middleware
+        // responses get written back to devzat after each event.
+        let (tx, rx) = mpsc::channel(1);
+
+        tx.send(ListenerClientData {
+            data: Some(Data::Listener(listener)),
+        })
+        .await
+        .map_err(|_| ClientError::StreamClosed)?;
+
+        let events = client
+            .register_listener(ReceiverStream::new(rx))
+            .await?
+            .into_inner();
+
+        Ok(EventStream {
+            tx,
+            events,
+            max_decoding_message_size: self.options.max_decoding_message_size,
+        })
+    }
+
+    /// Runs until devzat closes the stream: a clean close returns `Ok`, while a dropped
+    /// connection returns an error for which [ClientError::is_transient] is `true`.
+    ///
+    /// # Arguments
+    ///
+    /// `listener` - [Listener] struct containing information about the listener. An invalid
+    /// `regex` fails with [ClientError::InvalidRegex] before anything is sent to devzat. With
+    /// `once` set, this returns after the first event has been handled.
+    ///
+    /// `callback` - Asynchronous function to be executed. For middleware listeners the
+    /// returned value is sent back to devzat: `Some(msg)` replaces the message and `None`
+    /// leaves it untouched. Other listeners must return `None`, or this fails with
+    /// [ClientError::MiddlewareMisuse].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_listener(Listener::all(), |event| async move {
+    ///         eprintln!("room={}, from={}, msg={}", event.room, event.from, event.msg);
+    ///         None
+    ///     }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener<F, Fut>(&self, listener: Listener, callback: F) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        self.register_listener_cancellable(listener, CancellationToken::new(), callback)
+            .await
+            .map(|_| ())
+    }
+
+    /// Same as [register_listener](Self::register_listener), but the callback returns a
+    /// [Result]. Errors are logged with `tracing` when the feature is enabled, and a middleware
+    /// leaves the message untouched, as if the callback returned `Ok(None)`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_listener_fallible(Listener::all(), |event| async move {
+    ///         std::fs::write("last_message.txt", event.msg)?;
+    ///         Ok::<_, std::io::Error>(None)
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub async fn register_listener_fallible<F, Fut, E>(
+        &self,
+        listener: Listener,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Result<Option<String>, E>>,
+        E: std::fmt::Display,
+    {
+        self.register_listener(listener, move |event| {
+            let result = callback(event);
+
+            async move {
+                let result = result.await;
+
+                if let Err(err) = &result {
+                    warn!(error = %err, "listener callback failed");
+                }
+
+                result.unwrap_or_default()
+            }
+        })
+        .await
+    }
+
+    /// Same as [register_listener](Self::register_listener), but the callback is also handed
+    /// the groups captured by the regex of `listener`, as returned by [Event::captures].
+    ///
+    /// devzat uses Go's regex syntax, so the message is matched again here with the `regex`
+    /// crate. In the rare cases where devzat matched but the `regex` crate doesn't, the event is
+    /// skipped, and a middleware leaves the message untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let tickets = Listener::builder().regex(r"#(\d+)").build();
+    ///
+    /// client
+    ///     .register_listener_captures(tickets, |event, captures| async move {
+    ///         if let Some(Some(ticket)) = captures.get(1) {
+    ///             eprintln!("{} mentioned ticket {}", event.from, ticket);
+    ///         }
+    ///
+    ///         None
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener_captures<F, Fut>(
+        &self,
+        listener: Listener,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(Event, Vec<Option<String>>) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        let regex = Regex::new(listener.regex())?;
+
+        self.register_listener(listener, move |event| {
+            let result = event
+                .captures(&regex)
+                .map(|captures| callback(event, captures));
+
+            async move {
+                match result {
+                    Some(result) => result.await,
+                    None => None,
+                }
+            }
+        })
+        .await
+    }
+
+    /// Registers `listener` as a middleware, whatever its
+    /// [middleware](Listener::middleware) field, rewriting each message it matches with
+    /// `transform`: `Some(msg)` replaces the message and `None` lets it through unchanged.
+    ///
+    /// This is [Middleware::register] for a transform that doesn't need to await anything.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_middleware(Listener::builder().regex("heck").build(), |event| {
+    ///         Some(event.msg.replace("heck", "h*ck"))
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_middleware<F>(&self, listener: Listener, mut transform: F) -> PluginResult
+    where
+        F: FnMut(Event) -> Option<String>,
+    {
+        Middleware::new(listener)
+            .register(self, move |event| std::future::ready(transform(event)))
+            .await
+    }
+
+    /// Registers a single listener for messages matching any of `patterns`, and calls the
+    /// callback with the index of the first pattern the message matches.
+    ///
+    /// Every pattern is checked before registering, failing with [ClientError::InvalidRegex].
+    /// devzat is sent one regex alternating between all of them, so this only opens one
+    /// stream. Without any pattern, this returns right away.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let patterns = vec![String::from("(?i)rust"), String::from("(?i)ferris")];
+    ///
+    /// client
+    ///     .register_any(patterns, |event, pattern| async move {
+    ///         eprintln!("{} matched pattern {}", event.msg, pattern);
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_any<F, Fut>(&self, patterns: Vec<String>, mut callback: F) -> PluginResult
+    where
+        F: FnMut(Event, usize) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        if patterns.is_empty() {
+            return Ok(());
+        }
+
+        let set = RegexSet::new(&patterns)?;
+        let regex = patterns
+            .iter()
+            .map(|pattern| format!("(?:{})", pattern))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        self.register_listener(Listener::builder().regex(regex).build(), move |event| {
+            // devzat uses Go's regex syntax, so in the rare cases where it matched but the
+            // `regex` crate doesn't, there's no pattern to report and the event is skipped.
+            let matched = set.matches(&event.msg).into_iter().next();
+            let result = matched.map(|pattern| callback(event, pattern));
+
+            async move {
+                if let Some(result) = result {
+                    result.await;
+                }
+
+                None
+            }
+        })
+        .await
+    }
+
+    /// Same as [register_listener](Self::register_listener), but events sent by the same user
+    /// in the same room are coalesced: the callback is only called for the last one, once
+    /// `window` has passed without another.
+    ///
+    /// As the callback runs after devzat delivered the message, it can't rewrite it, and
+    /// middleware listeners leave every message untouched. Events still waiting when the
+    /// stream ends are handled right away.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_listener_debounced(Listener::all(), Duration::from_secs(2), |event| async move {
+    ///         eprintln!("{} stopped typing: {}", event.from, event.msg);
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener_debounced<F, Fut>(
+        &self,
+        listener: Listener,
+        window: Duration,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let mut events = self.listen(listener.clone()).await?;
+        let token = CancellationToken::new();
+        let mut pending = HashMap::new();
+
+        loop {
+            let deadline = pending.values().map(|(deadline, _)| *deadline).min();
+
+            tokio::select! {
+                event = events.next_or_cancelled(&token) => {
+                    let Some(event) = event? else { break };
+
+                    if listener.middleware() {
+                        events.respond(None).await?;
+                    }
+
+                    if self.options.bot_username.as_ref() != Some(&event.from) {
+                        let key = (event.room.clone(), event.from.clone());
+                        pending.insert(key, (Instant::now() + window, event));
+                    }
+                }
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let now = Instant::now();
+                    let due: Vec<_> = pending
+                        .iter()
+                        .filter(|(_, (deadline, _))| *deadline <= now)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+
+                    for key in due {
+                        if let Some((_, event)) = pending.remove(&key) {
+                            if self.options.timed(callback(event)).await.is_none() {
+                                warn!("listener callback timed out");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (_, event) in pending.into_values() {
+            if self.options.timed(callback(event)).await.is_none() {
+                warn!("listener callback timed out");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as [register_listener](Self::register_listener), but the callback is only called
+    /// for events sent to one of `rooms`. An empty `rooms` means all rooms. A name without the
+    /// `#` gets one, see [Room].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_listener_in_rooms(Listener::all(), vec!["#main".into()], |event| async move {
+    ///         eprintln!("{}: {}", event.from, event.msg);
+    ///         None
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener_in_rooms<F, Fut>(
+        &self,
+        listener: Listener,
+        rooms: Vec<Room>,
+        callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        let events = self.listen(listener.clone()).await?;
+
+        self.run_listener(
+            events,
+            &listener,
+            &rooms,
+            CancellationToken::new(),
+            &mut 0,
+            callback,
+        )
+        .await
+    }
+
+    /// Same as [register_listener](Self::register_listener), but stops listening once `token` is
+    /// cancelled. Returns the number of events the callback was called with.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, Listener};
+    /// # use tokio_util::sync::CancellationToken;
+    /// # async fn run(client: Client, listener: Listener) -> devzat_rs::PluginResult {
+    /// let token = CancellationToken::new();
+    /// let shutdown = token.clone();
+    ///
+    /// tokio::spawn(async move {
+    ///     tokio::signal::ctrl_c().await.unwrap();
+    ///     shutdown.cancel();
+    /// });
+    ///
+    /// let handled = client
+    ///     .register_listener_cancellable(listener, token, |event| async move {
+    ///         eprintln!("{}: {}", event.from, event.msg);
+    ///         None
+    ///     })
+    ///     .await?;
+    ///
+    /// eprintln!("Handled {} events.", handled);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener_cancellable<F, Fut>(
+        &self,
+        listener: Listener,
+        token: CancellationToken,
+        callback: F,
+    ) -> Result<u64, ClientError>
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        let events = self.listen(listener.clone()).await?;
+        let mut count = 0;
+
+        self.run_listener(events, &listener, &[], token, &mut count, callback)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Same as [register_listener](Self::register_listener), but when the connection to devzat
+    /// is lost the listener is registered again, waiting according to `backoff` between
+    /// attempts. Errors that aren't [transient](ClientError::is_transient), like an invalid
+    /// token, are still returned.
+    ///
+    /// Returns the number of events the callback was called with, across all registrations.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Backoff, Client, Listener};
+    /// # async fn run(client: Client, listener: Listener) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_listener_resilient(listener, Backoff::default(), |event| async move {
+    ///         eprintln!("{}: {}", event.from, event.msg);
+    ///         None
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_listener_resilient<F, Fut>(
+        &self,
+        listener: Listener,
+        backoff: Backoff,
+        mut callback: F,
+    ) -> Result<u64, ClientError>
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        let token = CancellationToken::new();
+        let mut delay = backoff.initial;
+        let mut count = 0;
+
+        loop {
+            let result = match self.listen(listener.clone()).await {
+                Ok(events) => {
+                    delay = backoff.initial;
+                    self.run_listener(
+                        events,
+                        &listener,
+                        &[],
+                        token.clone(),
+                        &mut count,
+                        &mut callback,
+                    )
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Err(err) if err.is_transient() => {
+                    warn!(error = %err, ?delay, "registration lost, registering again");
+                    tokio::time::sleep(delay).await;
+                    delay = backoff.next(delay);
+                }
+                result => return result.map(|_| count),
+            }
+        }
+    }
+
+    /// Spawns a task running `register` with a clone of the client, and running it again
+    /// whenever it fails with a [transient](ClientError::is_transient) error, waiting
+    /// according to `backoff` between attempts. This keeps any registration logic alive across
+    /// disconnects without having to write the loop.
+    ///
+    /// The returned [Supervisor] stops the task with [Supervisor::stop], and reports every
+    /// error through [Supervisor::next_error]. The task also ends once `register` returns `Ok`
+    /// or an error that isn't transient.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Backoff, Client, Listener};
+    /// # async fn run(client: Client) {
+    /// let mut supervisor = client.spawn_supervised(Backoff::default(), |client| async move {
+    ///     client
+    ///         .register_listener(Listener::all(), |event| async move {
+    ///             eprintln!("{}: {}", event.from, event.msg);
+    ///             None
+    ///         })
+    ///         .await
+    /// });
+    ///
+    /// while let Some(err) = supervisor.next_error().await {
+    ///     eprintln!("listener failed: {}", err);
+    /// }
+    /// # }
+    /// ```
+    ///
+    pub fn spawn_supervised<F, Fut>(&self, backoff: Backoff, register: F) -> Supervisor
+    where
+        F: FnMut(Client) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = PluginResult> + Send,
+    {
+        Supervisor::spawn(self.clone(), backoff, register)
+    }
+
+    /// Runs until devzat closes the stream: a clean close returns `Ok`, while a dropped
+    /// connection returns an error for which [ClientError::is_transient] is `true`.
+    ///
+    /// # Arguments
+    ///
+    /// `name` - Command name.
+    ///
+    /// `info` - Command information.
+    ///
+    /// `args_info` - Information about the command arguments.
+    ///
+    /// `callback` - Asynchronous function that will be ran on command invocation. Its result is
+    /// sent to the room the command was invoked in, unless it's empty or only whitespace, so
+    /// commands working silently can return an empty `String`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd("greet", "Greet someone.", "<name>", |event| async move {
+    ///         format!("Hello {}!", event.args)
+    ///     }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The callback may capture and mutate state, e.g. to count invocations:
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let mut count = 0;
+    ///
+    /// client
+    ///     .register_cmd("count", "Count invocations.", "", move |_| {
+    ///         count += 1;
+    ///         async move { format!("Invoked {} times.", count) }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The callback doesn't need to be `Copy` either, so it can own a client for another
+    /// service, such as an HTTP client or here a second devzat [Client]. Each future then gets
+    /// its own clone, as it may still be running when the callback is called again:
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let announcer = client.clone();
+    ///
+    /// client
+    ///     .register_cmd("announce", "Announce something.", "<text>", move |event| {
+    ///         let announcer = announcer.clone();
+    ///
+    ///         async move {
+    ///             match announcer.send_system_message("#main", event.args).await {
+    ///                 Ok(()) => String::from("Announced."),
+    ///                 Err(err) => format!("Couldn't announce it: {}", err),
+    ///             }
+    ///         }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        self.register_cmd_as(name, info, args_info, None, callback)
+            .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but the callback only borrows the
+    /// invocation. The room and invoker are then moved into the reply instead of being copied
+    /// for each invocation. The future can't borrow the invocation, so the callback takes what
+    /// it needs from it before returning.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_ref("greet", "Greet someone.", "<name>", |event| {
+    ///         let reply = format!("Hello {}!", event.args);
+    ///         async move { reply }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_ref<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(&CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd(
+            &cmd,
+            event,
+            None,
+            CancellationToken::new(),
+            &mut 0,
+            |event: &mut CmdInvocation| callback(event),
+        )
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but the callback returns a [Result]. Errors
+    /// are logged with `tracing` when the feature is enabled, and answered with a generic
+    /// error message instead of the reply, while later invocations keep being handled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_fallible("motd", "Show the message of the day.", "", |_| async move {
+    ///         std::fs::read_to_string("motd.txt")
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub async fn register_cmd_fallible<S, F, Fut, E>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = Result<String, E>>,
+        E: std::fmt::Display,
+    {
+        self.register_cmd(name, info, args_info, move |event| {
+            let result = callback(event);
+
+            async move {
+                let result = result.await;
+
+                if let Err(err) = &result {
+                    warn!(error = %err, "command callback failed");
+                }
+
+                result.unwrap_or_else(|_| String::from(CMD_ERROR_REPLY))
+            }
+        })
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but the callback returns a [CmdReply], which
+    /// can send the reply to another room or only show it to the user who invoked the command.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, CmdReply};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_reply("kick", "Kick someone.", "<name>", |event| async move {
+    ///         match event.args.is_empty() {
+    ///             true => CmdReply::new("Usage: kick <name>").ephemeral(),
+    ///             false => CmdReply::new(format!("{} kicked {}.", event.from, event.args))
+    ///                 .in_room("#mod-log"),
+    ///         }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_reply<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = CmdReply>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd(
+            &cmd,
+            event,
+            None,
+            CancellationToken::new(),
+            &mut 0,
+            by_value(callback),
+        )
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but the callback is only called when the
+    /// number of arguments matches `arity`. Other invocations, as well as ones with an
+    /// unterminated quote, get an ephemeral reply showing the usage of the command, built
+    /// from `name` and `args_info`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Arity, Client};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_checked(
+    ///         "swap",
+    ///         "Swap two words.",
+    ///         "<a> <b>",
+    ///         Arity::Exact(2),
+    ///         |event| async move {
+    ///             let args = event.parsed_args().unwrap_or_default();
+    ///             format!("{} {}", args[1], args[0])
+    ///         },
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_checked<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        arity: Arity,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let usage = CmdReply::new(cmd.usage()).ephemeral();
+        let event = self.open_cmd(&cmd).await?;
+
+        let checked = move |event: CmdInvocation| {
+            let result = match event.parsed_args() {
+                Ok(args) if arity.accepts(args.len()) => Some(callback(event)),
+                _ => None,
+            };
+            let usage = usage.clone();
+
+            async move {
+                match result {
+                    Some(result) => CmdReply::new(result.await),
+                    None => usage,
+                }
+            }
+        };
+
+        self.run_cmd(
+            &cmd,
+            event,
+            None,
+            CancellationToken::new(),
+            &mut 0,
+            by_value(checked),
+        )
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but only the users in `allowed` can run the
+    /// command, e.g. for moderation commands. Anyone else gets an ephemeral "Permission
+    /// denied." reply and the callback isn't called.
+    ///
+    /// An empty `allowed` means nobody can run the command.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # use std::collections::HashSet;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let mods = HashSet::from([String::from("Ada"), String::from("Grace")]);
+    ///
+    /// client
+    ///     .register_cmd_restricted("mute", "Mute someone.", "<name>", mods, |event| async move {
+    ///         format!("{} was muted.", event.args)
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_restricted<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        allowed: HashSet<String>,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        let restricted = move |event: CmdInvocation| {
+            let result = allowed.contains(&event.from).then(|| callback(event));
+
+            async move {
+                match result {
+                    Some(result) => CmdReply::new(result.await),
+                    None => CmdReply::new(CMD_DENIED_REPLY).ephemeral(),
+                }
+            }
+        };
+
+        self.run_cmd(
+            &cmd,
+            event,
+            None,
+            CancellationToken::new(),
+            &mut 0,
+            by_value(restricted),
+        )
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but replies are sent from `from` instead of
+    /// the default name.
+    ///
+    /// # Arguments
+    ///
+    /// `from` - Username the command replies will be sent from. `None` keeps the default.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_as(
+    ///         "greet",
+    ///         "Greet someone.",
+    ///         "<name>",
+    ///         Some(String::from("Rusty")),
+    ///         |event| async move { format!("Hello {}!", event.args) },
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_as<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        from: Option<String>,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd(
+            &cmd,
+            event,
+            from,
+            CancellationToken::new(),
+            &mut 0,
+            by_value(callback),
+        )
+        .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but stops handling invocations once `token`
+    /// is cancelled. Returns the number of invocations handled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # use tokio_util::sync::CancellationToken;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let token = CancellationToken::new();
+    ///
+    /// client
+    ///     .register_cmd_cancellable("greet", "Greet someone.", "<name>", token, |event| async move {
+    ///         format!("Hello {}!", event.args)
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_cancellable<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        token: CancellationToken,
+        callback: F,
+    ) -> Result<u64, ClientError>
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+        let mut count = 0;
+
+        self.run_cmd(&cmd, event, None, token, &mut count, by_value(callback))
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but when the connection to devzat is lost
+    /// the command is registered again, waiting according to `backoff` between attempts.
+    /// Errors that aren't [transient](ClientError::is_transient), like an invalid token, are
+    /// still returned.
+    ///
+    /// Returns the number of invocations handled, across all registrations.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Backoff, Client};
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    ///
+    /// client
+    ///     .register_cmd_resilient("greet", "Greet someone.", "<name>", backoff, |event| async move {
+    ///         format!("Hello {}!", event.args)
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_resilient<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        backoff: Backoff,
+        mut callback: F,
+    ) -> Result<u64, ClientError>
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let token = CancellationToken::new();
+        let mut delay = backoff.initial;
+        let mut count = 0;
+
+        loop {
+            let result = match self.open_cmd(&cmd).await {
+                Ok(event) => {
+                    delay = backoff.initial;
+                    let callback = by_value(&mut callback);
+                    self.run_cmd(&cmd, event, None, token.clone(), &mut count, callback)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Err(err) if err.is_transient() => {
+                    warn!(error = %err, ?delay, "registration lost, registering again");
+                    tokio::time::sleep(delay).await;
+                    delay = backoff.next(delay);
+                }
+                result => return result.map(|_| count),
+            }
+        }
+    }
+
+    /// Registers a command and keeps it registered for as long as the returned future runs,
+    /// which makes a whole bot with a single command. Stop it by dropping the future, e.g. on
+    /// Ctrl-C as below.
+    ///
+    /// Like [register_cmd_resilient](Self::register_cmd_resilient) with the default [Backoff],
+    /// the command is registered again when the connection is lost, and also when devzat
+    /// closes the stream, e.g. while it restarts. This only returns errors that aren't
+    /// [transient](ClientError::is_transient), like an invalid token.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use devzat_rs::{Client, ClientError};
+    ///
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() -> Result<(), ClientError> {
+    ///     let client = Client::builder()
+    ///         .host("https://devzat.hackclub.com:5556")
+    ///         .token("dvz.token@hello.world1234")
+    ///         .connect_lazy(true)
+    ///         .build()
+    ///         .await?;
+    ///
+    ///     let greet = client.serve_cmd("greet", "Greet someone.", "<name>", |event| async move {
+    ///         format!("Hello {}!", event.args)
+    ///     });
+    ///
+    ///     tokio::select! {
+    ///         result = greet => result,
+    ///         _ = tokio::signal::ctrl_c() => Ok(()),
+    ///     }
+    /// }
+    /// ```
+    ///
+    pub async fn serve_cmd<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let backoff = Backoff::default();
+
+        loop {
+            self.register_cmd_resilient(
+                cmd.name.clone(),
+                cmd.info.clone(),
+                cmd.args_info.clone(),
+                backoff,
+                &mut callback,
+            )
+            .await?;
+
+            debug!(name = %cmd.name, "stream closed by devzat, registering again");
+            tokio::time::sleep(backoff.initial).await;
+        }
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but each user can only invoke the command
+    /// once every `cooldown`. Invocations during the cooldown skip the callback and get an
+    /// ephemeral reply telling the user how long to wait.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_with_cooldown(
+    ///         "roll",
+    ///         "Roll a die.",
+    ///         "",
+    ///         Duration::from_secs(30),
+    ///         |_| async move { String::from("You rolled a 4.") },
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_with_cooldown<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        cooldown: Duration,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        let cooldown = Cooldown::new(cooldown);
+        self.run_cmd_with_cooldown(&cmd, event, cooldown, by_value(callback))
+            .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but the callback is handed a [Client] and
+    /// sends any messages itself instead of returning a single reply.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_with_client("announce", "Announce everywhere.", "<msg>", |event, client| async move {
+    ///         for room in ["#main", "#general"] {
+    ///             let _ = client
+    ///                 .send_message(room, None, event.args.clone(), None)
+    ///                 .await;
+    ///         }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_with_client<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation, Client) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd_with_client(&cmd, event, callback).await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but for commands that only have side
+    /// effects, such as calling a webhook: the callback returns nothing and no reply is sent.
+    /// The invoker is still told privately if the callback runs past the
+    /// [callback timeout](ClientBuilder::callback_timeout).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_action("report", "Report a problem to the admins.", "<text>", |event| async move {
+    ///         eprintln!("{} reported: {}", event.from, event.args);
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_action<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd_with_client(&cmd, event, move |event, _| callback(event))
+            .await
+    }
+
+    /// Same as [register_cmd](Self::register_cmd), but every invocation is handled in its own
+    /// task so a slow callback doesn't hold up the following invocations.
+    ///
+    /// Replies are sent as soon as each callback completes, so they may arrive out of
+    /// invocation order.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::Client;
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// client
+    ///     .register_cmd_concurrent("slow", "Answer after a while.", "", |_| async move {
+    ///         tokio::time::sleep(Duration::from_secs(5)).await;
+    ///         String::from("Done!")
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn register_cmd_concurrent<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String> + Send + 'static,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd_concurrent(&cmd, event, None, by_value(callback))
+            .await
+    }
+
+    /// Same as [register_cmd_concurrent](Self::register_cmd_concurrent), but stops once
+    /// `shutdown` is [triggered](Shutdown::trigger). Invocations received after that aren't
+    /// handled, while the callbacks already running are awaited for up to the grace period of
+    /// `shutdown`, so their replies are still sent. Callbacks still running after it are
+    /// dropped.
+    ///
+    /// See [Shutdown] for an example.
+    pub async fn register_cmd_concurrent_graceful<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        shutdown: Shutdown,
+        callback: F,
+    ) -> PluginResult
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String> + Send + 'static,
+    {
+        let cmd = CmdDef::new(name, info, args_info);
+        let event = self.open_cmd(&cmd).await?;
+
+        self.run_cmd_concurrent(&cmd, event, Some(shutdown), by_value(callback))
+            .await
+    }
+
+    /// Registers a command and returns the stream of its invocations.
+    async fn open_cmd(&self, cmd: &CmdDef) -> Result<Streaming<CmdInvocation>, ClientError> {
+        let mut client = self.client.clone();
+
+        Ok(client.register_cmd(cmd.clone()).await?.into_inner())
+    }
+
+    /// Waits for the next message on `stream`, or `None` once the stream ends or `token` is
+    /// cancelled.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn next_event<T: prost::Message + Default>(
+        &self,
+        stream: &mut Streaming<T>,
+        token: &CancellationToken,
+    ) -> Result<Option<T>, ClientError> {
+        let event = tokio::select! {
+            event = stream.message() => event,
+            _ = token.cancelled() => {
+                debug!("registration cancelled");
+                return Ok(None);
+            }
+        };
+
+        match &event {
+            Ok(Some(event)) => {
+                Options::check_size(event.encoded_len(), self.options.max_decoding_message_size)?
+            }
+            Ok(None) => {
+                debug!("stream closed by the server");
+            }
+            Err(status) => {
+                warn!(error = %status, "stream failed");
+            }
+        }
+
+        Ok(event?)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(regex = listener.regex(), middleware = listener.middleware()))
+    )]
+    async fn run_listener<F, Fut>(
+        &self,
+        mut events: EventStream,
+        listener: &Listener,
+        rooms: &[Room],
+        token: CancellationToken,
+        count: &mut u64,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: std::future::Future<Output = Option<String>>,
+    {
+        while let Some(event) = events.next_or_cancelled(&token).await? {
+            debug!(room = %event.room, from = %event.from, "received event");
+
+            let skip = (!rooms.is_empty() && !rooms.iter().any(|room| room.as_str() == event.room))
+                || self.options.bot_username.as_ref() == Some(&event.from);
+            let result = if skip {
+                None
+            } else {
+                *count += 1;
+
+                let result = self.options.timed(callback(event)).await;
+
+                if result.is_none() {
+                    warn!("listener callback timed out");
+                }
+
+                result.flatten()
+            };
+
+            if listener.middleware() {
+                // devzat waits for a response to every event sent to a middleware, so `None` is
+                // still written back to let the original message through.
+                events.respond(result).await?;
+            } else if result.is_some() {
+                return Err(ClientError::MiddlewareMisuse);
+            }
+
+            if listener.once() && !skip {
+                if listener.middleware() {
+                    events.ready().await?;
+                }
+
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(command = %cmd.name))
+    )]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn run_cmd<F, Fut, R>(
+        &self,
+        cmd: &CmdDef,
+        mut event: Streaming<CmdInvocation>,
+        from: Option<String>,
+        token: CancellationToken,
+        count: &mut u64,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(&mut CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = R>,
+        R: Into<CmdReply>,
+    {
+        while let Some(mut event) = self.next_event(&mut event, &token).await? {
+            debug!(room = %event.room, from = %event.from, "received invocation");
+            count!("devzat_cmd_invocations_total", "command" => cmd.name.clone());
+
+            *count += 1;
+
+            // The callback only borrows the invocation, so its room and invoker are moved into
+            // the reply afterwards rather than copied beforehand.
+            match self.options.timed(callback(&mut event)).await {
+                Some(reply) => {
+                    self.send_reply(reply.into(), event.room, event.from, from.clone())
+                        .await?
+                }
+                None => {
+                    self.cmd_timed_out(event.room, event.from, from.clone())
+                        .await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(command = %cmd.name))
+    )]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn run_cmd_with_cooldown<F, Fut>(
+        &self,
+        cmd: &CmdDef,
+        mut event: Streaming<CmdInvocation>,
+        mut cooldown: Cooldown,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(&mut CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        let token = CancellationToken::new();
+
+        while let Some(mut event) = self.next_event(&mut event, &token).await? {
+            debug!(room = %event.room, from = %event.from, "received invocation");
+            count!("devzat_cmd_invocations_total", "command" => cmd.name.clone());
+
+            if let Err(remaining) = cooldown.check(&event.from) {
+                // Round up, so users aren't told to wait 0 seconds.
+                let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+                let msg = format!("Please wait {} seconds before using this again.", secs);
+                self.send_ephemeral(event.room, event.from, msg, None)
+                    .await?;
+                continue;
+            }
+
+            match self.options.timed(callback(&mut event)).await {
+                Some(result) => {
+                    self.send_reply(result.into(), event.room, event.from, None)
+                        .await?
+                }
+                None => self.cmd_timed_out(event.room, event.from, None).await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(command = %cmd.name))
+    )]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn run_cmd_with_client<F, Fut>(
+        &self,
+        cmd: &CmdDef,
+        mut event: Streaming<CmdInvocation>,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(CmdInvocation, Client) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let token = CancellationToken::new();
+
+        while let Some(event) = self.next_event(&mut event, &token).await? {
+            debug!(room = %event.room, from = %event.from, "received invocation");
+            count!("devzat_cmd_invocations_total", "command" => cmd.name.clone());
+
+            // The room and invoker are only needed to tell the invoker the callback timed out,
+            // which can't happen without a timeout.
+            let target = self
+                .options
+                .callback_timeout
+                .map(|_| (event.room.clone(), event.from.clone()));
+
+            let result = self.options.timed(callback(event, self.clone())).await;
+
+            if let (None, Some((room, invoker))) = (result, target) {
+                self.cmd_timed_out(room, invoker, None).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(command = %cmd.name))
+    )]
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn run_cmd_concurrent<F, Fut>(
+        &self,
+        cmd: &CmdDef,
+        mut event: Streaming<CmdInvocation>,
+        shutdown: Option<Shutdown>,
+        mut callback: F,
+    ) -> PluginResult
+    where
+        F: FnMut(&mut CmdInvocation) -> Fut,
+        Fut: std::future::Future<Output = String> + Send + 'static,
+    {
+        let token = shutdown
+            .as_ref()
+            .map_or_else(CancellationToken::new, |shutdown| shutdown.token.clone());
+        let mut tasks = JoinSet::new();
+
+        loop {
+            tokio::select! {
+                event = self.next_event(&mut event, &token) => match event? {
+                    Some(mut event) => {
+                        debug!(room = %event.room, from = %event.from, "received invocation");
+                        count!("devzat_cmd_invocations_total", "command" => cmd.name.clone());
+
+                        // The next invocation is only read once this one could run, so a burst
+                        // of invocations waits on the stream instead of piling up as tasks.
+                        // Waiting after reading rather than before keeps an idle command from
+                        // holding a permit the other commands need.
+                        let permit = tokio::select! {
+                            permit = self.options.callback_permit() => permit,
+                            _ = token.cancelled() => break,
+                        };
+
+                        let client = self.clone();
+                        let result = callback(&mut event);
+                        let (room, invoker) = (event.room, event.from);
+
+                        let name = || format!("devzat-rs cmd {}", cmd.name);
+
+                        task::spawn_on(&mut tasks, name, async move {
+                            let result = match client.options.timed(result).await {
+                                Some(result) => client.send_reply(result.into(), room, invoker, None).await,
+                                None => client.cmd_timed_out(room, invoker, None).await,
+                            };
+
+                            drop(permit);
+                            result
+                        });
+                    }
+                    None => break,
+                },
+                Some(result) = tasks.join_next() => join_result(result)?,
+            }
+        }
+
+        let drain = async {
+            while let Some(result) = tasks.join_next().await {
+                join_result(result)?;
+            }
+
+            Ok(())
+        };
+
+        match shutdown {
+            Some(shutdown) => match tokio::time::timeout(shutdown.grace, drain).await {
+                Ok(result) => result,
+                Err(_) => {
+                    // Dropping the tasks aborts the callbacks that are still running.
+                    warn!("grace period elapsed, abandoning the running callbacks");
+                    Ok(())
+                }
+            },
+            None => drain.await,
+        }
+    }
+}
+
+impl Client {
+    /// Sends the reply to a command invoked in `room` by `invoker`, once decorated.
+    async fn send_reply(
+        &self,
+        reply: CmdReply,
+        room: String,
+        invoker: String,
+        from: Option<String>,
+    ) -> PluginResult {
+        match self.options.decorate(reply, &invoker) {
+            Some(reply) => self.send(reply.into_message(room, invoker, from)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Tells `invoker` that their command timed out.
+    async fn cmd_timed_out(
+        &self,
+        room: String,
+        invoker: String,
+        from: Option<String>,
+    ) -> PluginResult {
+        warn!("command callback timed out");
+
+        self.send_ephemeral(room, invoker, String::from(CMD_TIMEOUT_REPLY), from)
+            .await
+    }
+}
+
+impl CmdDef {
+    fn new<S: Into<String>>(name: S, info: S, args_info: S) -> Self {
+        Self {
+            name: name.into(),
+            info: info.into(),
+            args_info: args_info.into(),
+        }
+    }
+
+    /// How to invoke the command, e.g. `Usage: greet <name>`.
+    fn usage(&self) -> String {
+        match self.args_info.is_empty() {
+            true => format!("Usage: {}", self.name),
+            false => format!("Usage: {} {}", self.name, self.args_info),
+        }
+    }
+}
+
+/// Adapts a callback taking the invocation by value to the command loops, which only lend it.
+/// The callback gets the arguments, which the loops don't need anymore, along with a copy of the
+/// room and invoker, which they still reply to.
+pub(crate) fn by_value<F, Fut>(mut callback: F) -> impl FnMut(&mut CmdInvocation) -> Fut
+where
+    F: FnMut(CmdInvocation) -> Fut,
+{
+    move |event: &mut CmdInvocation| {
+        callback(CmdInvocation {
+            room: event.room.clone(),
+            from: event.from.clone(),
+            args: std::mem::take(&mut event.args),
+        })
+    }
+}
+
+/// Unwraps the result of a spawned callback task, resuming the panic if the callback panicked.
+fn join_result(result: Result<PluginResult, JoinError>) -> PluginResult {
+    match result {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
     }
 }
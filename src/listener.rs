@@ -0,0 +1,154 @@
+use std::future::Future;
+
+use crate::{
+    plugin::{Event, Listener},
+    Client, PluginResult,
+};
+
+impl Listener {
+    /// A listener that's called for every message, without acting as a middleware.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> ListenerBuilder {
+        ListenerBuilder::new()
+    }
+}
+
+/// Builder for a [Listener].
+///
+/// # Examples
+///
+/// ```
+/// # use devzat_rs::ListenerBuilder;
+/// let listener = ListenerBuilder::new()
+///     .middleware(true)
+///     .regex("^!ping")
+///     .build();
+///
+/// assert!(listener.middleware());
+/// assert!(!listener.once());
+/// assert_eq!(listener.regex(), "^!ping");
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct ListenerBuilder {
+    listener: Listener,
+}
+
+impl ListenerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the listener can rewrite messages before they're sent.
+    pub fn middleware(mut self, middleware: bool) -> Self {
+        self.listener.middleware = Some(middleware);
+        self
+    }
+
+    /// Whether the listener should only be called for the first matching message.
+    pub fn once(mut self, once: bool) -> Self {
+        self.listener.once = Some(once);
+        self
+    }
+
+    /// Only call the listener for messages matching `regex`, without slashes or flags.
+    pub fn regex<S: Into<String>>(mut self, regex: S) -> Self {
+        self.listener.regex = Some(regex.into());
+        self
+    }
+
+    pub fn build(self) -> Listener {
+        self.listener
+    }
+}
+
+/// A [Listener] that's always a middleware, whose callback decides what happens to each
+/// message.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, Listener, Middleware};
+/// # async fn run(client: Client) -> devzat_rs::PluginResult {
+/// Middleware::new(Listener::builder().regex("heck").build())
+///     .register(&client, |event| async move { Some(event.msg.replace("heck", "h*ck")) })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct Middleware(Listener);
+
+impl Middleware {
+    /// Turns `listener` into a middleware, whatever its [middleware](Listener::middleware) field.
+    pub fn new(mut listener: Listener) -> Self {
+        listener.middleware = Some(true);
+        Self(listener)
+    }
+
+    pub fn listener(&self) -> &Listener {
+        &self.0
+    }
+
+    /// Registers the middleware, see [Client::register_listener]. The message is replaced by
+    /// the one returned by `callback`, if any.
+    pub async fn register<F, Fut>(self, client: &Client, callback: F) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: Future<Output = Option<String>>,
+    {
+        client.register_listener(self.0, callback).await
+    }
+}
+
+/// A [Listener] that's never a middleware, so its callback can't return a message.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, Listener, PlainListener};
+/// # async fn run(client: Client) -> devzat_rs::PluginResult {
+/// PlainListener::new(Listener::all())
+///     .register(&client, |event| async move { println!("{}: {}", event.from, event.msg) })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct PlainListener(Listener);
+
+impl PlainListener {
+    /// Makes sure `listener` isn't a middleware, whatever its
+    /// [middleware](Listener::middleware) field.
+    pub fn new(mut listener: Listener) -> Self {
+        listener.middleware = Some(false);
+        Self(listener)
+    }
+
+    pub fn listener(&self) -> &Listener {
+        &self.0
+    }
+
+    /// Registers the listener, see [Client::register_listener].
+    pub async fn register<F, Fut>(self, client: &Client, mut callback: F) -> PluginResult
+    where
+        F: FnMut(Event) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        client
+            .register_listener(self.0, move |event| {
+                let handled = callback(event);
+
+                async move {
+                    handled.await;
+                    None
+                }
+            })
+            .await
+    }
+}
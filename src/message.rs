@@ -0,0 +1,74 @@
+use crate::{
+    plugin::{Message, MessageRes},
+    Room,
+};
+
+impl Message {
+    /// Starts building a message for `room`. A name without the `#` gets one, see [Room].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::Message;
+    /// let msg = Message::to("main").text("Hello!").from("Rusty").build();
+    ///
+    /// assert_eq!(msg.room, "#main");
+    /// assert_eq!(msg.from.as_deref(), Some("Rusty"));
+    /// assert_eq!(msg.ephemeral_to, None);
+    /// ```
+    ///
+    pub fn to(room: impl Into<Room>) -> MessageBuilder {
+        MessageBuilder {
+            msg: Message {
+                room: room.into().into(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Builder for a [Message], created with [Message::to].
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    msg: Message,
+}
+
+impl MessageBuilder {
+    /// The actual text that will be sent.
+    pub fn text<S: Into<String>>(mut self, text: S) -> Self {
+        self.msg.msg = text.into();
+        self
+    }
+
+    /// Username the message will be sent from.
+    pub fn from<S: Into<String>>(mut self, from: S) -> Self {
+        self.msg.from = Some(from.into());
+        self
+    }
+
+    /// Only show the message to this user.
+    pub fn ephemeral_to<S: Into<String>>(mut self, user: S) -> Self {
+        self.msg.ephemeral_to = Some(user.into());
+        self
+    }
+
+    pub fn build(self) -> Message {
+        self.msg
+    }
+}
+
+/// Answer of devzat to a message, returned by
+/// [Client::send_message_ack](crate::Client::send_message_ack).
+///
+/// The protocol doesn't carry anything in it yet, so this only confirms the message was
+/// accepted. Fields may be added as the protocol grows, which is why it can't be built outside
+/// this crate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MessageAck {}
+
+impl From<MessageRes> for MessageAck {
+    fn from(_: MessageRes) -> Self {
+        Self {}
+    }
+}
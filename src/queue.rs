@@ -0,0 +1,94 @@
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::{
+    mpsc::{self, error::SendError},
+    oneshot,
+};
+
+type Job = BoxFuture<'static, ()>;
+
+/// Number of messages a room queues before sending to it waits for room in the queue.
+const CAPACITY: usize = 32;
+
+/// Queue of outgoing messages for each room, shared by every clone of a [Client](crate::Client).
+///
+/// Each room with messages queued gets a background task sending them one at a time, in the
+/// order they were queued, so the replies of callbacks running concurrently don't overtake each
+/// other. Rooms don't wait on each other. A room's task stops, and the room is forgotten, once
+/// its queue is empty, so rooms only cost anything while messages are being sent to them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RoomQueues {
+    rooms: Arc<Mutex<HashMap<String, mpsc::Sender<Job>>>>,
+}
+
+impl RoomQueues {
+    /// Queues `task` behind the ones already queued for `room` and waits for its output, or
+    /// `None` if the queue stopped. Waits for room first if the queue is full. `task` doesn't
+    /// run if this is dropped while it's queued.
+    pub(crate) async fn run<F>(&self, room: &str, task: F) -> Option<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        let mut job: Job = Box::pin(async move {
+            if !tx.is_closed() {
+                let _ = tx.send(task.await);
+            }
+        });
+
+        // The queue may stop between getting it and sending to it, once it ran out of jobs, in
+        // which case the job goes to a new one.
+        while let Err(SendError(unsent)) = self.queue(room).send(job).await {
+            job = unsent;
+        }
+
+        rx.await.ok()
+    }
+
+    fn queue(&self, room: &str) -> mpsc::Sender<Job> {
+        let mut rooms = lock(&self.rooms);
+
+        if let Some(queue) = rooms.get(room).filter(|queue| !queue.is_closed()) {
+            return queue.clone();
+        }
+
+        let (tx, mut rx) = mpsc::channel::<Job>(CAPACITY);
+        let queues = self.rooms.clone();
+        let name = String::from(room);
+
+        crate::task::spawn(|| format!("devzat-rs room queue {}", room), async move {
+            // The first job is sent right after the queue is created.
+            let mut next = rx.recv().await;
+
+            while let Some(job) = next {
+                job.await;
+                next = rx.try_recv().ok();
+            }
+
+            // Jobs sent from now on go to a new queue, while the ones sent since the queue ran
+            // out still run here.
+            {
+                let mut rooms = lock(&queues);
+                rooms.remove(&name);
+                rx.close();
+            }
+
+            while let Some(job) = rx.recv().await {
+                job.await;
+            }
+        });
+
+        rooms.insert(String::from(room), tx.clone());
+        tx
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,46 @@
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+/// Token bucket shared by every clone of a [Client](crate::Client).
+///
+/// The bucket holds up to `per_second` permits and gets one back every `1 / per_second`
+/// seconds from a background task, which stops once the last clone is dropped.
+#[derive(Clone, Debug)]
+pub(crate) struct RateLimiter {
+    permits: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(per_second: u32) -> Self {
+        let capacity = per_second as usize;
+        let permits = Arc::new(Semaphore::new(capacity));
+        let bucket = Arc::downgrade(&permits);
+
+        crate::task::spawn(|| String::from("devzat-rs rate limiter"), async move {
+            let mut refill = tokio::time::interval(Duration::from_secs(1) / per_second);
+            refill.tick().await;
+
+            loop {
+                refill.tick().await;
+
+                match bucket.upgrade() {
+                    Some(permits) if permits.available_permits() < capacity => {
+                        permits.add_permits(1)
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        });
+
+        Self { permits }
+    }
+
+    /// Waits until a permit is available and takes it.
+    pub(crate) async fn acquire(&self) {
+        // The semaphore is never closed, so this can't fail.
+        if let Ok(permit) = self.permits.acquire().await {
+            permit.forget();
+        }
+    }
+}
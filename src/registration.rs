@@ -0,0 +1,31 @@
+use tokio_util::sync::CancellationToken;
+
+/// Handle stopping a single command of a [CommandRouter](crate::CommandRouter), returned by
+/// [CommandRouter::registration](crate::CommandRouter::registration) and
+/// [RouterHandle::add_command](crate::RouterHandle::add_command).
+///
+/// Clones stop the same command.
+#[derive(Clone, Debug, Default)]
+pub struct Registration {
+    token: CancellationToken,
+}
+
+impl Registration {
+    /// Stops handling invocations of the command, which then stops being registered on
+    /// devzat. Stopping a command that's already stopped does nothing.
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether [stop](Self::stop) has been called.
+    pub fn is_stopped(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Token cancelled by [stop](Self::stop), e.g. to stop a listener registered with
+    /// [Client::register_listener_cancellable](crate::Client::register_listener_cancellable)
+    /// at the same time.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, sync::Arc};
+
+use crate::{plugin::Message, Room};
+
+/// Reply of a command registered with [Client::register_cmd_reply](crate::Client::register_cmd_reply).
+///
+/// # Examples
+///
+/// ```
+/// # use devzat_rs::CmdReply;
+/// let reply = CmdReply::new("Ada was kicked.").in_room("mod-log");
+///
+/// assert_eq!(reply.room.as_deref(), Some("#mod-log"));
+/// assert!(!reply.ephemeral);
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CmdReply {
+    /// Room the reply is sent to, or `None` for the room the command was invoked in.
+    pub room: Option<String>,
+    /// The actual text that will be sent.
+    pub msg: String,
+    /// Whether only the user who invoked the command sees the reply.
+    pub ephemeral: bool,
+}
+
+impl CmdReply {
+    /// A reply sent to everyone in the room the command was invoked in.
+    pub fn new<S: Into<String>>(msg: S) -> Self {
+        Self {
+            msg: msg.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sends the reply to `room` instead. A name without the `#` gets one, see [Room].
+    pub fn in_room(mut self, room: impl Into<Room>) -> Self {
+        self.room = Some(room.into().into());
+        self
+    }
+
+    /// Only shows the reply to the user who invoked the command.
+    pub fn ephemeral(mut self) -> Self {
+        self.ephemeral = true;
+        self
+    }
+
+    /// Whether the reply is empty or only whitespace, in which case it isn't sent.
+    pub(crate) fn is_blank(&self) -> bool {
+        self.msg.trim().is_empty()
+    }
+
+    /// Turns the reply into a message, for a command invoked in `room` by `invoker`.
+    pub(crate) fn into_message(
+        self,
+        room: String,
+        invoker: String,
+        from: Option<String>,
+    ) -> Message {
+        Message {
+            room: self.room.unwrap_or(room),
+            from,
+            msg: self.msg,
+            ephemeral_to: self.ephemeral.then_some(invoker),
+        }
+    }
+}
+
+impl From<String> for CmdReply {
+    fn from(msg: String) -> Self {
+        Self::new(msg)
+    }
+}
+
+impl From<&str> for CmdReply {
+    fn from(msg: &str) -> Self {
+        Self::new(msg)
+    }
+}
+
+/// Function set with [ClientBuilder::reply_decorator](crate::ClientBuilder::reply_decorator),
+/// rewriting every command reply before it's sent.
+#[derive(Clone)]
+pub(crate) struct ReplyDecorator(pub(crate) Arc<dyn Fn(String) -> String + Send + Sync>);
+
+impl fmt::Debug for ReplyDecorator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReplyDecorator")
+    }
+}
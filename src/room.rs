@@ -0,0 +1,85 @@
+use std::fmt;
+
+use crate::ClientError;
+
+/// Name of a devzat room, always starting with `#`.
+///
+/// The send methods of [Client](crate::Client) take anything turning into a `Room`, so plain
+/// strings keep working: a name without the `#`, which would otherwise silently send the
+/// message nowhere, gets one.
+///
+/// # Examples
+///
+/// ```
+/// # use devzat_rs::Room;
+/// assert_eq!(Room::new("main").as_str(), "#main");
+/// assert_eq!(Room::new("#main").as_str(), "#main");
+/// assert_eq!(Room::from(String::from("mods")), Room::new("#mods"));
+///
+/// assert_eq!(Room::parse("#main").unwrap(), Room::new("main"));
+/// assert!(Room::parse("main").is_err());
+/// assert!(Room::parse("#").is_err());
+/// assert!(Room::parse("").is_err());
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Room(String);
+
+impl Room {
+    /// The room called `name`, adding the `#` if it's missing. Nothing else is checked, so an
+    /// empty `name` gives `#`, which isn't a room devzat has: use [parse](Self::parse) for
+    /// names that need to be validated, e.g. ones typed by users.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        let name = name.into();
+
+        match name.starts_with('#') {
+            true => Self(name),
+            false => Self(format!("#{}", name)),
+        }
+    }
+
+    /// The room called `name`, which must be written with its `#` and can't be empty nor
+    /// contain whitespace. Fails with [ClientError::InvalidRoom] otherwise.
+    pub fn parse<S: Into<String>>(name: S) -> Result<Self, ClientError> {
+        let name = name.into();
+
+        match name.strip_prefix('#') {
+            Some(rest) if !rest.is_empty() && !rest.contains(char::is_whitespace) => Ok(Self(name)),
+            _ => Err(ClientError::InvalidRoom(name)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Room {
+    fn from(name: String) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<&str> for Room {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<Room> for String {
+    fn from(room: Room) -> Self {
+        room.0
+    }
+}
+
+impl AsRef<str> for Room {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Room {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,540 @@
+use futures_util::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    by_value,
+    plugin::{CmdDef, CmdInvocation},
+    ArgError, Client, ClientError, CmdReply, PluginResult, Registration,
+};
+
+type Handler = Box<dyn FnMut(CmdInvocation) -> BoxFuture<'static, CmdReply> + Send>;
+
+type Fallback =
+    Arc<Mutex<dyn FnMut(CmdInvocation, ArgError) -> BoxFuture<'static, CmdReply> + Send>>;
+
+type Layer = Arc<dyn Fn(CmdInvocation, Next) -> BoxFuture<'static, CmdReply> + Send + Sync>;
+
+struct Route {
+    info: String,
+    args_info: String,
+    handler: Handler,
+    registration: Registration,
+}
+
+impl Route {
+    fn new<F, Fut>(info: String, args_info: String, mut handler: F) -> Self
+    where
+        F: FnMut(CmdInvocation) -> Fut + Send + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        Self {
+            info,
+            args_info,
+            handler: Box::new(move |event| {
+                let reply = handler(event);
+                Box::pin(async move { CmdReply::new(reply.await) })
+            }),
+            registration: Registration::default(),
+        }
+    }
+
+    /// Line describing the command in the help.
+    fn describe(&self, name: &str) -> String {
+        match self.args_info.is_empty() {
+            true => format!("{} - {}", name, self.info),
+            false => format!("{} {} - {}", name, self.args_info, self.info),
+        }
+    }
+}
+
+/// The help, from the [describe](Route::describe) line of each command sorted by name, shared by
+/// [CommandRouter::help] and the command added by [with_help](CommandRouter::with_help).
+fn join_help<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> String {
+    lines
+        .into_iter()
+        .map(|line| String::from(line.as_ref()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Command sent by a [RouterHandle] to the running [CommandRouter].
+struct NewCommand {
+    name: String,
+    route: Route,
+    added: oneshot::Sender<Result<Registration, ClientError>>,
+}
+
+/// Set of commands registered and served together, each invocation being routed to the
+/// handler of its command.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, CommandRouter};
+/// # async fn run(client: Client) -> devzat_rs::PluginResult {
+/// CommandRouter::new()
+///     .add("greet", "Greet someone.", "<name>", |event| async move {
+///         format!("Hello {}!", event.args)
+///     })
+///     .add("ping", "Check the bot is alive.", "", |_| async move {
+///         String::from("pong")
+///     })
+///     .with_help("help")
+///     .run(&client)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[derive(Default)]
+pub struct CommandRouter {
+    routes: BTreeMap<String, Route>,
+    help: Option<String>,
+    layers: Vec<Layer>,
+    fallback: Option<Fallback>,
+    commands: Option<(
+        mpsc::UnboundedSender<NewCommand>,
+        mpsc::UnboundedReceiver<NewCommand>,
+    )>,
+}
+
+impl CommandRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command, see [Client::register_cmd]. Adding a command with the same `name` again
+    /// replaces it.
+    pub fn add<S, F, Fut>(mut self, name: S, info: S, args_info: S, handler: F) -> Self
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut + Send + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let route = Route::new(info.into(), args_info.into(), handler);
+
+        self.routes.insert(name.into(), route);
+        self
+    }
+
+    /// Wraps every command with `layer`, for logic shared by all of them such as permission
+    /// checks, logging or metrics. This includes the [help](Self::with_help) command and the
+    /// ones added later through a [RouterHandle].
+    ///
+    /// The layer is called with each invocation and a [Next] running the rest of the chain:
+    /// it can call [Next::run] to get the reply of the command and change it, or reply on its
+    /// own without running the command. Layers run in the order they're added, the first one
+    /// wrapping all the others.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, CmdReply, CommandRouter};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// CommandRouter::new()
+    ///     .add("ban", "Ban someone.", "<name>", |event| async move {
+    ///         format!("{} was banned.", event.args)
+    ///     })
+    ///     .layer(|event, next| async move {
+    ///         match event.from.as_str() {
+    ///             "troll" => CmdReply::new("You can't use this bot.").ephemeral(),
+    ///             _ => next.run(event).await,
+    ///         }
+    ///     })
+    ///     .run(&client)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn layer<F, Fut>(mut self, layer: F) -> Self
+    where
+        F: Fn(CmdInvocation, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CmdReply> + Send + 'static,
+    {
+        self.layers
+            .push(Arc::new(move |event, next| Box::pin(layer(event, next))));
+        self
+    }
+
+    /// Replies with `fallback` instead of running the command when the arguments of an
+    /// invocation can't be split into words, like [CmdInvocation::parsed_args] does, e.g. to
+    /// point the user to the help.
+    ///
+    /// devzat only sends the invocations of the commands a plugin registered, so there's no
+    /// such thing as an unknown command here: the fallback is about arguments instead. It
+    /// applies to every command, including the ones added later through a [RouterHandle], and
+    /// runs inside the [layers](Self::layer). Commands taking free text, where a lone quote is
+    /// fine, are better off in a router without a fallback.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, CommandRouter};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// CommandRouter::new()
+    ///     .add("greet", "Greet someone.", "<name>", |event| async move {
+    ///         format!("Hello {}!", event.parsed_args().unwrap_or_default().join(" and "))
+    ///     })
+    ///     .with_help("help")
+    ///     .fallback(|event, err| async move {
+    ///         format!("{}: {}, try /help", event.from, err)
+    ///     })
+    ///     .run(&client)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn fallback<F, Fut>(mut self, mut fallback: F) -> Self
+    where
+        F: FnMut(CmdInvocation, ArgError) -> Fut + Send + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        self.fallback = Some(Arc::new(Mutex::new(move |event, err| {
+            let reply = fallback(event, err);
+            Box::pin(async move { CmdReply::new(reply.await) }) as BoxFuture<'static, CmdReply>
+        })));
+        self
+    }
+
+    /// Adds a `name` command replying with the [help](Self::help) of every other command,
+    /// including the ones added later through a [RouterHandle].
+    pub fn with_help<S: Into<String>>(mut self, name: S) -> Self {
+        self.help = Some(name.into());
+        self
+    }
+
+    /// Returns a handle adding commands while the router is running. Commands added before
+    /// [run](Self::run) is called are registered as soon as it is.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, CommandRouter};
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let mut router = CommandRouter::new();
+    /// let handle = router.handle();
+    ///
+    /// tokio::spawn(async move {
+    ///     // e.g. once the config has been reloaded.
+    ///     handle
+    ///         .add_command("uptime", "Show the uptime.", "", |_| async move {
+    ///             String::from("Up for 3 days.")
+    ///         })
+    ///         .await
+    /// });
+    ///
+    /// router.run(&client).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn handle(&mut self) -> RouterHandle {
+        let (commands, _) = self.commands.get_or_insert_with(mpsc::unbounded_channel);
+
+        RouterHandle {
+            commands: commands.clone(),
+        }
+    }
+
+    /// Returns a handle stopping the `name` command on its own, or `None` if there's no such
+    /// command.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use devzat_rs::{Client, CommandRouter};
+    /// # use std::time::Duration;
+    /// # async fn run(client: Client) -> devzat_rs::PluginResult {
+    /// let router = CommandRouter::new()
+    ///     .add("vote", "Vote for the next map.", "<map>", |event| async move {
+    ///         format!("{} voted for {}.", event.from, event.args)
+    ///     })
+    ///     .add("ping", "Check the bot is alive.", "", |_| async move {
+    ///         String::from("pong")
+    ///     });
+    ///
+    /// if let Some(vote) = router.registration("vote") {
+    ///     tokio::spawn(async move {
+    ///         tokio::time::sleep(Duration::from_secs(300)).await;
+    ///         vote.stop();
+    ///     });
+    /// }
+    ///
+    /// router.run(&client).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn registration(&self, name: &str) -> Option<Registration> {
+        self.routes
+            .get(name)
+            .map(|route| route.registration.clone())
+    }
+
+    /// Lists every command with its arguments and information, sorted by name. The command
+    /// added by [with_help](Self::with_help) is left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use devzat_rs::CommandRouter;
+    /// let router = CommandRouter::new()
+    ///     .add("ping", "Check the bot is alive.", "", |_| async move { String::new() })
+    ///     .add("greet", "Greet someone.", "<name>", |_| async move { String::new() })
+    ///     .with_help("help");
+    ///
+    /// assert_eq!(
+    ///     router.help(),
+    ///     "greet <name> - Greet someone.\nping - Check the bot is alive."
+    /// );
+    /// ```
+    ///
+    pub fn help(&self) -> String {
+        let lines = self
+            .routes
+            .iter()
+            .filter(|(name, _)| self.help.as_ref() != Some(*name))
+            .map(|(name, route)| route.describe(name));
+
+        join_help(lines)
+    }
+
+    /// Registers every command and handles their invocations until they all complete. While
+    /// a [RouterHandle] is alive, this also waits for the commands it adds.
+    ///
+    /// The commands are independent: one failing to register or losing its stream doesn't
+    /// stop the others, and the first error is only returned once every command is done.
+    pub async fn run(mut self, client: &Client) -> PluginResult {
+        let mut running = Running {
+            client: client.clone(),
+            help: self.help.clone(),
+            layers: self.layers.clone().into(),
+            fallback: self.fallback.clone(),
+            listing: Arc::default(),
+            registrations: FuturesUnordered::new(),
+        };
+
+        if let Some(name) = self.help.clone() {
+            // Shared with the running router, so that commands added later are listed too.
+            let listing = running.listing.clone();
+
+            self = self.add(
+                name,
+                String::from("List the commands."),
+                String::new(),
+                move |_| {
+                    let help = match listing.lock() {
+                        Ok(listing) => join_help(listing.values()),
+                        Err(_) => String::new(),
+                    };
+
+                    async move { help }
+                },
+            );
+        }
+
+        for (name, route) in self.routes {
+            running.start(name, route);
+        }
+
+        let mut commands = self.commands.map(|(_, commands)| commands);
+        let mut result = Ok(());
+
+        loop {
+            tokio::select! {
+                Some((name, registration)) = running.registrations.next() => {
+                    running.finished(&name);
+
+                    if result.is_ok() {
+                        result = registration;
+                    }
+                }
+                command = async { commands.as_mut()?.recv().await }, if commands.is_some() => {
+                    let Some(NewCommand { name, route, added }) = command else {
+                        commands = None;
+                        continue;
+                    };
+
+                    let _ = added.send(match running.is_running(&name) {
+                        true => Err(ClientError::CommandExists(name)),
+                        false => {
+                            let registration = route.registration.clone();
+                            running.start(name, route);
+                            Ok(registration)
+                        }
+                    });
+                }
+                else => break,
+            }
+        }
+
+        result
+    }
+}
+
+/// Commands of a [CommandRouter] being run.
+struct Running {
+    client: Client,
+    help: Option<String>,
+    layers: Arc<[Layer]>,
+    fallback: Option<Fallback>,
+    /// Help of each running command other than the help one, by name.
+    listing: Arc<Mutex<BTreeMap<String, String>>>,
+    registrations: FuturesUnordered<BoxFuture<'static, (String, PluginResult)>>,
+}
+
+impl Running {
+    fn start(&mut self, name: String, route: Route) {
+        if let Ok(mut listing) = self.listing.lock() {
+            if self.help.as_ref() != Some(&name) {
+                listing.insert(name.clone(), route.describe(&name));
+            }
+        }
+
+        let client = self.client.clone();
+        let Route {
+            info,
+            args_info,
+            handler,
+            registration,
+        } = route;
+
+        let handler = match self.fallback.clone() {
+            Some(fallback) => with_fallback(handler, fallback),
+            None => handler,
+        };
+
+        let handler = Arc::new(Mutex::new(handler));
+        let layers = self.layers.clone();
+        let layered = move |event| {
+            let next = Next {
+                handler: handler.clone(),
+                layers: layers.clone(),
+                index: 0,
+            };
+
+            next.run(event)
+        };
+
+        self.registrations.push(Box::pin(async move {
+            let cmd = CmdDef::new(name.clone(), info, args_info);
+            let result = match client.open_cmd(&cmd).await {
+                Ok(event) => {
+                    let token = registration.token();
+                    client
+                        .run_cmd(&cmd, event, None, token, &mut 0, by_value(layered))
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+
+            (name, result)
+        }));
+    }
+
+    fn finished(&mut self, name: &str) {
+        if let Ok(mut listing) = self.listing.lock() {
+            listing.remove(name);
+        }
+    }
+
+    fn is_running(&self, name: &str) -> bool {
+        self.help.as_deref() == Some(name)
+            || self
+                .listing
+                .lock()
+                .is_ok_and(|listing| listing.contains_key(name))
+    }
+}
+
+/// Runs `handler`, or `fallback` if the arguments don't parse.
+fn with_fallback(mut handler: Handler, fallback: Fallback) -> Handler {
+    Box::new(move |event| match event.parsed_args() {
+        Ok(_) => handler(event),
+        Err(err) => match fallback.lock() {
+            Ok(mut fallback) => fallback(event, err),
+            Err(poisoned) => poisoned.into_inner()(event, err),
+        },
+    })
+}
+
+/// Rest of the chain of [layers](CommandRouter::layer) wrapping a command, down to the command
+/// itself.
+pub struct Next {
+    handler: Arc<Mutex<Handler>>,
+    layers: Arc<[Layer]>,
+    index: usize,
+}
+
+impl Next {
+    /// Runs the following layers and the command with `event`, and returns the reply.
+    pub async fn run(self, event: CmdInvocation) -> CmdReply {
+        let Some(layer) = self.layers.get(self.index).cloned() else {
+            // A handler that panicked while locked is still usable, as it's only locked to
+            // create its future.
+            let reply = match self.handler.lock() {
+                Ok(mut handler) => handler(event),
+                Err(err) => err.into_inner()(event),
+            };
+
+            return reply.await;
+        };
+
+        let next = Self {
+            index: self.index + 1,
+            ..self
+        };
+
+        layer(event, next).await
+    }
+}
+
+/// Handle adding commands to a running [CommandRouter], created with [CommandRouter::handle].
+#[derive(Clone)]
+pub struct RouterHandle {
+    commands: mpsc::UnboundedSender<NewCommand>,
+}
+
+impl RouterHandle {
+    /// Adds a command to the router, see [CommandRouter::add], and returns a handle stopping
+    /// it on its own.
+    ///
+    /// Fails with [ClientError::CommandExists] if a command with the same `name` is already
+    /// running, and with [ClientError::RouterStopped] once the router is done. Errors while
+    /// registering the command are returned by [CommandRouter::run], like for the others.
+    pub async fn add_command<S, F, Fut>(
+        &self,
+        name: S,
+        info: S,
+        args_info: S,
+        handler: F,
+    ) -> Result<Registration, ClientError>
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut + Send + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let (added, result) = oneshot::channel();
+        let command = NewCommand {
+            name: name.into(),
+            route: Route::new(info.into(), args_info.into(), handler),
+            added,
+        };
+
+        self.commands
+            .send(command)
+            .map_err(|_| ClientError::RouterStopped)?;
+
+        result.await.map_err(|_| ClientError::RouterStopped)?
+    }
+}
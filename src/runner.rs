@@ -0,0 +1,85 @@
+use futures_util::future::{try_join_all, BoxFuture};
+use std::future::Future;
+
+use crate::{
+    plugin::{CmdInvocation, Event, Listener},
+    Client, PluginResult,
+};
+
+/// Runs several command and listener registrations of a [Client] at once.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, Listener, PluginRunner};
+/// # async fn run(client: Client) -> devzat_rs::PluginResult {
+/// PluginRunner::new(client)
+///     .add_cmd("greet", "Greet someone.", "<name>", |event| async move {
+///         format!("Hello {}!", event.args)
+///     })
+///     .add_listener(Listener::all(), |event| async move {
+///         eprintln!("{}: {}", event.from, event.msg);
+///         None
+///     })
+///     .run()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct PluginRunner {
+    client: Client,
+    registrations: Vec<BoxFuture<'static, PluginResult>>,
+}
+
+impl PluginRunner {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Adds a command, see [Client::register_cmd].
+    pub fn add_cmd<S, F, Fut>(self, name: S, info: S, args_info: S, callback: F) -> Self
+    where
+        S: Into<String>,
+        F: FnMut(CmdInvocation) -> Fut + Send + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let (name, info, args_info) = (name.into(), info.into(), args_info.into());
+        let client = self.client.clone();
+
+        self.add_registration(
+            async move { client.register_cmd(name, info, args_info, callback).await },
+        )
+    }
+
+    /// Adds a listener, see [Client::register_listener].
+    pub fn add_listener<F, Fut>(self, listener: Listener, callback: F) -> Self
+    where
+        F: FnMut(Event) -> Fut + Send + 'static,
+        Fut: Future<Output = Option<String>> + Send + 'static,
+    {
+        let client = self.client.clone();
+
+        self.add_registration(async move { client.register_listener(listener, callback).await })
+    }
+
+    /// Adds any other registration, e.g. one built with the [Client] passed to
+    /// [new](Self::new).
+    pub fn add_registration<Fut>(mut self, registration: Fut) -> Self
+    where
+        Fut: Future<Output = PluginResult> + Send + 'static,
+    {
+        self.registrations.push(Box::pin(registration));
+        self
+    }
+
+    /// Drives every registration until they all complete, or returns the first error.
+    pub async fn run(self) -> PluginResult {
+        try_join_all(self.registrations).await?;
+
+        Ok(())
+    }
+}
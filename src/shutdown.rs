@@ -0,0 +1,59 @@
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Coordinates a graceful shutdown of
+/// [register_cmd_concurrent_graceful](crate::Client::register_cmd_concurrent_graceful): once
+/// [triggered](Self::trigger), no new invocations are handled, while the callbacks already
+/// running get up to `grace` to finish and send their reply.
+///
+/// Clones trigger the same shutdown.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use devzat_rs::{Client, Shutdown};
+/// # use std::time::Duration;
+/// # async fn run(client: Client) -> devzat_rs::PluginResult {
+/// let shutdown = Shutdown::new(Duration::from_secs(10));
+/// let trigger = shutdown.clone();
+///
+/// tokio::spawn(async move {
+///     tokio::signal::ctrl_c().await.ok();
+///     trigger.trigger();
+/// });
+///
+/// client
+///     .register_cmd_concurrent_graceful("slow", "Answer after a while.", "", shutdown, |_| async {
+///         tokio::time::sleep(Duration::from_secs(5)).await;
+///         String::from("Done!")
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    pub(crate) token: CancellationToken,
+    pub(crate) grace: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            grace,
+        }
+    }
+
+    /// Stops handling new invocations and starts the grace period. Triggering it again does
+    /// nothing.
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether [trigger](Self::trigger) has been called.
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
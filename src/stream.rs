@@ -0,0 +1,96 @@
+use futures_util::{Stream, StreamExt};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tonic::Streaming;
+
+use crate::{
+    plugin::{listener_client_data::Data, Event, ListenerClientData, MiddlewareResponse},
+    ClientError, Options, PluginResult,
+};
+
+/// Stream of the events sent to a listener, returned by [Client::listen](crate::Client::listen).
+pub struct EventStream {
+    pub(crate) tx: mpsc::Sender<ListenerClientData>,
+    pub(crate) events: Streaming<Event>,
+    pub(crate) max_decoding_message_size: Option<usize>,
+}
+
+impl EventStream {
+    /// Answers the last event of a middleware listener: `Some(msg)` replaces the message and
+    /// `None` leaves it untouched. devzat waits for an answer to every event before delivering
+    /// the message, so middleware listeners must call this once per event.
+    pub async fn respond(&mut self, msg: Option<String>) -> PluginResult {
+        self.tx
+            .send(ListenerClientData {
+                data: Some(Data::Response(MiddlewareResponse { msg })),
+            })
+            .await
+            .map_err(|_| ClientError::StreamClosed)
+    }
+
+    /// Waits until the stream is ready for another response, which means the last one sent
+    /// with [respond](Self::respond) was handed to the transport, e.g. before dropping the
+    /// stream of a `once` middleware, whose last response would otherwise be dropped along with
+    /// it.
+    ///
+    /// This doesn't confirm that devzat received the response: the protocol doesn't
+    /// acknowledge them, and the transport may still be writing it to the connection.
+    pub async fn ready(&mut self) -> PluginResult {
+        // Responses are queued one at a time, so there's room in the queue again once the
+        // transport took the last one.
+        self.tx
+            .reserve()
+            .await
+            .map(drop)
+            .map_err(|_| ClientError::StreamClosed)
+    }
+
+    /// Waits for the next event, or `None` once the stream ends or `token` is cancelled.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) async fn next_or_cancelled(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Result<Option<Event>, ClientError> {
+        let event = tokio::select! {
+            event = self.next() => event.transpose(),
+            _ = token.cancelled() => {
+                debug!("registration cancelled");
+                return Ok(None);
+            }
+        };
+
+        match &event {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                debug!("stream closed by the server");
+            }
+            Err(err) => {
+                warn!(error = %err, "stream failed");
+            }
+        }
+
+        event
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let limit = self.max_decoding_message_size;
+
+        Pin::new(&mut self.events).poll_next(cx).map(|event| {
+            event.map(|event| {
+                let event = event?;
+                Options::check_size(prost::Message::encoded_len(&event), limit)?;
+                count!("devzat_events_received_total");
+
+                Ok(event)
+            })
+        })
+    }
+}
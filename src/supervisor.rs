@@ -0,0 +1,81 @@
+use std::future::Future;
+use tokio::{
+    sync::mpsc,
+    time::{sleep, Instant},
+};
+
+use crate::{Backoff, Client, ClientError, PluginResult, Registration};
+
+/// Handle to a registration kept alive by [Client::spawn_supervised].
+///
+/// Dropping the handle leaves the registration running, it's only stopped by
+/// [stop](Self::stop).
+#[derive(Debug)]
+pub struct Supervisor {
+    registration: Registration,
+    errors: mpsc::UnboundedReceiver<ClientError>,
+}
+
+impl Supervisor {
+    pub(crate) fn spawn<F, Fut>(client: Client, backoff: Backoff, mut register: F) -> Self
+    where
+        F: FnMut(Client) -> Fut + Send + 'static,
+        Fut: Future<Output = PluginResult> + Send,
+    {
+        let registration = Registration::default();
+        let token = registration.token();
+        let (errors, receiver) = mpsc::unbounded_channel();
+
+        crate::task::spawn(|| String::from("devzat-rs supervisor"), async move {
+            let mut delay = backoff.initial;
+
+            loop {
+                let started = Instant::now();
+                let result = tokio::select! {
+                    result = register(client.clone()) => result,
+                    _ = token.cancelled() => break,
+                };
+
+                let Err(err) = result else { break };
+                let transient = err.is_transient();
+                let _ = errors.send(err);
+
+                if !transient {
+                    break;
+                }
+
+                // A registration that ran for a while was connected, so the next failure
+                // isn't part of the same outage.
+                if started.elapsed() >= backoff.max {
+                    delay = backoff.initial;
+                }
+
+                tokio::select! {
+                    _ = sleep(delay) => {}
+                    _ = token.cancelled() => break,
+                }
+
+                delay = backoff.next(delay);
+            }
+        });
+
+        Self {
+            registration,
+            errors: receiver,
+        }
+    }
+
+    /// Stops the registration, dropping it wherever it's at. Stopping it again does nothing.
+    pub fn stop(&self) {
+        self.registration.stop();
+    }
+
+    /// Waits for the next error the registration ended with. Transient errors are followed by
+    /// another attempt, while other errors stop the supervisor.
+    ///
+    /// Returns `None` once the supervisor is done: after [stop](Self::stop), when the
+    /// registration completes without an error, or after an error that isn't transient.
+    pub async fn next_error(&mut self) -> Option<ClientError> {
+        self.errors.recv().await
+    }
+}
@@ -0,0 +1,49 @@
+//! Spawning of the tasks of the client, named after what they serve with the `task-names`
+//! feature so that they can be told apart in tokio-console. Naming tasks is an unstable tokio
+//! API, so without `--cfg tokio_unstable` they're spawned unnamed, as without the feature.
+
+use std::future::Future;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Spawns `task` on the runtime, like `tokio::spawn`.
+#[cfg_attr(
+    not(all(feature = "task-names", tokio_unstable)),
+    allow(unused_variables)
+)]
+pub(crate) fn spawn<F>(name: impl FnOnce() -> String, task: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "task-names", tokio_unstable))]
+    let handle = tokio::task::Builder::new()
+        .name(&name())
+        .spawn(task)
+        .expect("failed to spawn task");
+
+    #[cfg(not(all(feature = "task-names", tokio_unstable)))]
+    let handle = tokio::spawn(task);
+
+    handle
+}
+
+/// Spawns `task` on `tasks`, like [JoinSet::spawn].
+#[cfg_attr(
+    not(all(feature = "task-names", tokio_unstable)),
+    allow(unused_variables)
+)]
+pub(crate) fn spawn_on<T, F>(tasks: &mut JoinSet<T>, name: impl FnOnce() -> String, task: F)
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    #[cfg(all(feature = "task-names", tokio_unstable))]
+    tasks
+        .build_task()
+        .name(&name())
+        .spawn(task)
+        .expect("failed to spawn task");
+
+    #[cfg(not(all(feature = "task-names", tokio_unstable)))]
+    tasks.spawn(task);
+}
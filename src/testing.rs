@@ -0,0 +1,186 @@
+//! Stand-in for a [Client](crate::Client) to test bots without a devzat server. Requires the
+//! `testing` feature.
+//!
+//! Bots written against [DevzatClient] can be handed a [MockClient], which records the messages
+//! it's asked to send. Commands and listeners are tested by feeding them invocations and events
+//! with [MockClient::invoke] and [MockClient::emit], which handle them like
+//! [register_cmd](crate::Client::register_cmd) and
+//! [register_listener](crate::Client::register_listener) would.
+//!
+//! To test a [Client](crate::Client) itself, down to the gRPC streams, [MockServer] runs a
+//! devzat plugin server in the test.
+//!
+//! # Examples
+//!
+//! ```
+//! use devzat_rs::{testing::MockClient, CmdInvocation, Event, Listener};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> devzat_rs::PluginResult {
+//! let client = MockClient::new();
+//! let invocation = CmdInvocation {
+//!     room: "#main".into(),
+//!     from: "Ada".into(),
+//!     args: "world".into(),
+//! };
+//!
+//! client
+//!     .invoke(invocation, |event| async move { format!("Hello {}!", event.args) })
+//!     .await?;
+//!
+//! let sent = client.sent();
+//! assert_eq!(sent[0].room, "#main");
+//! assert_eq!(sent[0].msg, "Hello world!");
+//!
+//! let censor = Listener::builder().middleware(true).regex("heck").build();
+//! let event = Event {
+//!     room: "#main".into(),
+//!     from: "Ada".into(),
+//!     msg: "what the heck".into(),
+//! };
+//!
+//! let msg = client
+//!     .emit(&censor, event, |event| async move { Some(event.msg.replace("heck", "h*ck")) })
+//!     .await?;
+//! assert_eq!(msg.as_deref(), Some("what the h*ck"));
+//! # Ok(())
+//! # }
+//! ```
+
+use futures_util::future::BoxFuture;
+use regex::Regex;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    plugin::{CmdInvocation, Event, Listener, Message},
+    ClientError, CmdReply, DevzatClient, PluginResult,
+};
+
+mod server;
+
+pub use server::MockServer;
+
+/// [DevzatClient] recording the messages sent instead of sending them. See the
+/// [module docs](self).
+///
+/// Clones share the same recorded messages.
+///
+/// # Examples
+///
+/// ```
+/// use devzat_rs::{testing::MockClient, DevzatClient};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let client = MockClient::new();
+/// let rooms = vec!["#main".into(), "#general".into(), "#mods".into()];
+///
+/// let results = client.broadcast(rooms, None, "Hi all!".into()).await;
+/// assert!(results.iter().all(Result::is_ok));
+///
+/// let rooms: Vec<_> = client.sent().into_iter().map(|msg| msg.room).collect();
+/// assert_eq!(rooms, ["#main", "#general", "#mods"]);
+/// # }
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct MockClient {
+    sent: Arc<Mutex<Vec<Message>>>,
+}
+
+impl MockClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages sent so far, oldest first.
+    pub fn sent(&self) -> Vec<Message> {
+        self.lock().clone()
+    }
+
+    /// Same as [sent](Self::sent), but forgets the messages, so the next call only returns the
+    /// ones sent in between.
+    pub fn take_sent(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    /// Calls a command `callback` with `invocation` and records its reply, like
+    /// [register_cmd](crate::Client::register_cmd) does for each invocation. Like there,
+    /// empty or whitespace-only replies aren't sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use devzat_rs::{testing::MockClient, CmdInvocation};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> devzat_rs::PluginResult {
+    /// let client = MockClient::new();
+    ///
+    /// client
+    ///     .invoke(CmdInvocation::default(), |_| async move { String::from(" \n") })
+    ///     .await?;
+    ///
+    /// assert!(client.sent().is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn invoke<F, Fut, R>(&self, invocation: CmdInvocation, callback: F) -> PluginResult
+    where
+        F: FnOnce(CmdInvocation) -> Fut,
+        Fut: Future<Output = R>,
+        R: Into<CmdReply>,
+    {
+        let room = invocation.room.clone();
+        let invoker = invocation.from.clone();
+        let reply: CmdReply = callback(invocation).await.into();
+
+        if reply.is_blank() {
+            return Ok(());
+        }
+
+        DevzatClient::send(self, reply.into_message(room, invoker, None)).await
+    }
+
+    /// Calls a listener `callback` with `event`, if it matches the regex of `listener`, and
+    /// returns the message a middleware replaces it with.
+    ///
+    /// Fails with [ClientError::InvalidRegex] if the regex doesn't compile, and with
+    /// [ClientError::MiddlewareMisuse] if `callback` returns a message although `listener`
+    /// isn't a middleware.
+    pub async fn emit<F, Fut>(
+        &self,
+        listener: &Listener,
+        event: Event,
+        callback: F,
+    ) -> Result<Option<String>, ClientError>
+    where
+        F: FnOnce(Event) -> Fut,
+        Fut: Future<Output = Option<String>>,
+    {
+        if !Regex::new(listener.regex())?.is_match(&event.msg) {
+            return Ok(None);
+        }
+
+        match callback(event).await {
+            Some(_) if !listener.middleware() => Err(ClientError::MiddlewareMisuse),
+            result => Ok(result),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Message>> {
+        // A test panicking while holding the lock shouldn't hide what was sent.
+        self.sent.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl DevzatClient for MockClient {
+    fn send(&self, msg: Message) -> BoxFuture<'_, PluginResult> {
+        self.lock().push(msg);
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,480 @@
+use futures_util::stream::BoxStream;
+use regex::Regex;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, Notify},
+};
+use tokio_stream::wrappers::{TcpListenerStream, UnboundedReceiverStream};
+use tokio_util::sync::CancellationToken;
+use tonic::{metadata::MetadataMap, transport::Server, Request, Response, Status, Streaming};
+
+use crate::{
+    plugin::{
+        listener_client_data::Data,
+        plugin_server::{Plugin, PluginServer},
+        CmdDef, CmdInvocation, Event, Listener, ListenerClientData, Message, MessageRes,
+    },
+    Client, ClientBuilder, ClientError,
+};
+
+/// devzat plugin server running in the test itself, to test a [Client] end to end over a real
+/// connection.
+///
+/// It records the messages sent, the middleware responses and the headers of every request, and
+/// drives the registered commands and listeners with [invoke](Self::invoke) and
+/// [emit](Self::emit). Their streams can be ended cleanly or with an error, like when devzat
+/// restarts or the connection drops, and sending messages can be made to fail or to be slow.
+/// The server stops once dropped.
+///
+/// # Examples
+///
+/// ```
+/// use devzat_rs::{testing::MockServer, CmdInvocation};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let server = MockServer::start().await?;
+/// let client = server.client().await?;
+///
+/// tokio::spawn(async move {
+///     client
+///         .register_cmd("ping", "Check the bot is alive.", "", |_| async move {
+///             String::from("pong")
+///         })
+///         .await
+/// });
+///
+/// let invocation = CmdInvocation {
+///     room: "#main".into(),
+///     from: "Ada".into(),
+///     args: String::new(),
+/// };
+/// server.invoke("ping", invocation).await;
+///
+/// let sent = server.wait_for_messages(1).await;
+/// assert_eq!(sent[0].room, "#main");
+/// assert_eq!(sent[0].msg, "pong");
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    shutdown: CancellationToken,
+}
+
+impl MockServer {
+    /// Token of the clients built with [builder](Self::builder).
+    pub const TOKEN: &'static str = "dvz.token@mock.server";
+
+    /// Starts a server on a free local port.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_at(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    /// Starts a server on `addr`, e.g. one a client was already told to connect to.
+    pub async fn start_at(addr: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        let shutdown = CancellationToken::new();
+
+        let plugin = PluginServer::new(MockPlugin {
+            shared: shared.clone(),
+        });
+        let stopped = shutdown.clone();
+
+        #[cfg(feature = "gzip")]
+        let plugin = plugin.accept_gzip();
+
+        crate::task::spawn(|| String::from("devzat-rs mock server"), async move {
+            let _ = Server::builder()
+                .add_service(plugin)
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                    stopped.cancelled().await
+                })
+                .await;
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            shutdown,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Address to give to [ClientBuilder::host], like `http://127.0.0.1:5556`.
+    pub fn host(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Builder of a client for this server, authenticating with [TOKEN](Self::TOKEN), to
+    /// change other settings before building it.
+    pub fn builder(&self) -> ClientBuilder {
+        ClientBuilder::new().host(self.host()).token(Self::TOKEN)
+    }
+
+    /// Client connected to this server.
+    pub async fn client(&self) -> Result<Client, ClientError> {
+        self.builder().build().await
+    }
+
+    /// Messages sent so far, oldest first.
+    pub fn messages(&self) -> Vec<Message> {
+        self.shared.lock().messages.clone()
+    }
+
+    /// Waits until at least `count` messages were sent, and returns them.
+    pub async fn wait_for_messages(&self, count: usize) -> Vec<Message> {
+        self.shared
+            .wait_for(|state| (state.messages.len() >= count).then(|| state.messages.clone()))
+            .await
+    }
+
+    /// Responses of the middlewares to the events sent with [emit](Self::emit), oldest first:
+    /// `Some(msg)` for a replaced message and `None` for one left untouched.
+    pub fn responses(&self) -> Vec<Option<String>> {
+        self.shared.lock().responses.clone()
+    }
+
+    /// Value of the `name` header of every request so far, oldest first, or `None` for the
+    /// requests without it.
+    pub fn headers(&self, name: &str) -> Vec<Option<String>> {
+        self.shared
+            .lock()
+            .headers
+            .iter()
+            .map(|headers| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            })
+            .collect()
+    }
+
+    /// Address of the client of every request so far, oldest first, to tell apart the requests
+    /// sharing a connection from the ones on a connection of their own.
+    pub fn peers(&self) -> Vec<Option<SocketAddr>> {
+        self.shared.lock().peers.clone()
+    }
+
+    /// Value of the `authorization` header of every request so far, oldest first.
+    pub fn authorizations(&self) -> Vec<String> {
+        self.headers("authorization")
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect()
+    }
+
+    /// Value of the `grpc-encoding` header of every request so far, oldest first, like `gzip`
+    /// for a compressed request and `None` for an uncompressed one.
+    pub fn encodings(&self) -> Vec<Option<String>> {
+        self.headers("grpc-encoding")
+    }
+
+    /// Fails the next message sent with `status` instead of recording it. Calling this several
+    /// times fails as many messages, in order.
+    pub fn fail_next_message(&self, status: Status) {
+        self.shared.update(|state| state.failures.push_back(status));
+    }
+
+    /// Waits `delay` before answering each message, like a server under load.
+    pub fn delay_messages(&self, delay: Duration) {
+        self.shared.update(|state| state.delay = delay);
+    }
+
+    /// Waits until the `name` command is registered, and returns its definition.
+    pub async fn wait_for_command(&self, name: &str) -> CmdDef {
+        self.shared
+            .wait_for(|state| state.command(name).map(|command| command.def.clone()))
+            .await
+    }
+
+    /// Sends `invocation` to the `name` command, once it's registered.
+    pub async fn invoke(&self, name: &str, invocation: CmdInvocation) {
+        let invocations = self
+            .shared
+            .wait_for(|state| {
+                state
+                    .command(name)
+                    .map(|command| command.invocations.clone())
+            })
+            .await;
+
+        let _ = invocations.send(Ok(invocation));
+    }
+
+    /// Ends the stream of the `name` command, like devzat does when it shuts down. The command
+    /// counts as registered again once the client registers it anew.
+    pub fn close_command(&self, name: &str) {
+        self.shared.update(|state| {
+            state.commands.remove(name);
+        });
+    }
+
+    /// Ends the stream of the `name` command with `status`, like a dropped connection does.
+    /// The command counts as registered again once the client registers it anew.
+    pub fn fail_command(&self, name: &str, status: Status) {
+        self.shared.update(|state| {
+            if let Some(command) = state.commands.remove(name) {
+                let _ = command.invocations.send(Err(status));
+            }
+        });
+    }
+
+    /// Waits until at least `count` listeners are registered.
+    pub async fn wait_for_listeners(&self, count: usize) {
+        self.shared
+            .wait_for(|state| {
+                state.listeners.retain(|stream| !stream.events.is_closed());
+                (state.listeners.len() >= count).then_some(())
+            })
+            .await
+    }
+
+    /// Ends the stream of every listener, like devzat does when it shuts down.
+    pub fn close_listeners(&self) {
+        self.shared.update(|state| state.listeners.clear());
+    }
+
+    /// Ends the stream of every listener with `status`, like a dropped connection does.
+    pub fn fail_listeners(&self, status: Status) {
+        self.shared.update(|state| {
+            for stream in state.listeners.drain(..) {
+                let status = Status::new(status.code(), status.message());
+                let _ = stream.events.send(Err(status));
+            }
+        });
+    }
+
+    /// Sends `event` to every listener whose regex matches its message, in the order they
+    /// were registered, and returns the message as changed by the middlewares. Each middleware
+    /// gets the message left by the previous one, and is waited for before moving on.
+    ///
+    /// Unlike devzat, `once` listeners aren't removed after their first event, so that tests
+    /// can check the client stops listening by itself.
+    pub async fn emit(&self, event: Event) -> String {
+        let streams: Vec<_> = {
+            let mut state = self.shared.lock();
+            state.listeners.retain(|stream| !stream.events.is_closed());
+
+            state
+                .listeners
+                .iter()
+                .filter(|stream| stream.matches(&event.msg))
+                .cloned()
+                .collect()
+        };
+
+        let mut msg = event.msg.clone();
+
+        for stream in streams {
+            let event = Event {
+                msg: msg.clone(),
+                ..event.clone()
+            };
+
+            if stream.events.send(Ok(event)).is_err() || !stream.listener.middleware() {
+                continue;
+            }
+
+            let response = stream.responses.lock().await.message().await;
+
+            if let Ok(Some(ListenerClientData {
+                data: Some(Data::Response(response)),
+            })) = response
+            {
+                self.shared
+                    .update(|state| state.responses.push(response.msg.clone()));
+
+                if let Some(changed) = response.msg {
+                    msg = changed;
+                }
+            }
+        }
+
+        msg
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// State of a [MockServer], shared with the service answering the requests.
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A test panicking while holding the lock shouldn't hide what was recorded.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn update(&self, update: impl FnOnce(&mut State)) {
+        update(&mut self.lock());
+        self.changed.notify_waiters();
+    }
+
+    /// Waits until `ready` returns something, calling it again after every change.
+    async fn wait_for<T>(&self, mut ready: impl FnMut(&mut State) -> Option<T>) -> T {
+        loop {
+            // Created before checking, so that a change in between isn't missed.
+            let changed = self.changed.notified();
+            let value = ready(&mut self.lock());
+
+            if let Some(value) = value {
+                return value;
+            }
+
+            changed.await;
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    messages: Vec<Message>,
+    responses: Vec<Option<String>>,
+    headers: Vec<MetadataMap>,
+    peers: Vec<Option<SocketAddr>>,
+    failures: VecDeque<Status>,
+    delay: Duration,
+    commands: HashMap<String, Command>,
+    listeners: Vec<ListenerStream>,
+}
+
+impl State {
+    /// The `name` command, if it's registered and its stream still open.
+    fn command(&self, name: &str) -> Option<&Command> {
+        self.commands
+            .get(name)
+            .filter(|command| !command.invocations.is_closed())
+    }
+}
+
+struct Command {
+    def: CmdDef,
+    invocations: mpsc::UnboundedSender<Result<CmdInvocation, Status>>,
+}
+
+#[derive(Clone)]
+struct ListenerStream {
+    listener: Listener,
+    events: mpsc::UnboundedSender<Result<Event, Status>>,
+    responses: Arc<tokio::sync::Mutex<Streaming<ListenerClientData>>>,
+}
+
+impl ListenerStream {
+    fn matches(&self, msg: &str) -> bool {
+        Regex::new(self.listener.regex()).is_ok_and(|regex| regex.is_match(msg))
+    }
+}
+
+struct MockPlugin {
+    shared: Arc<Shared>,
+}
+
+impl MockPlugin {
+    fn record<T>(&self, request: &Request<T>) {
+        let headers = request.metadata().clone();
+        let peer = request.remote_addr();
+
+        self.shared.update(|state| {
+            state.headers.push(headers);
+            state.peers.push(peer);
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl Plugin for MockPlugin {
+    type RegisterListenerStream = BoxStream<'static, Result<Event, Status>>;
+
+    async fn register_listener(
+        &self,
+        request: Request<Streaming<ListenerClientData>>,
+    ) -> Result<Response<Self::RegisterListenerStream>, Status> {
+        self.record(&request);
+
+        let mut responses = request.into_inner();
+        let listener = match responses.message().await? {
+            Some(ListenerClientData {
+                data: Some(Data::Listener(listener)),
+            }) => listener,
+            _ => return Err(Status::invalid_argument("expected a listener")),
+        };
+
+        let (events, rx) = mpsc::unbounded_channel();
+        let stream = ListenerStream {
+            listener,
+            events,
+            responses: Arc::new(tokio::sync::Mutex::new(responses)),
+        };
+
+        self.shared.update(|state| state.listeners.push(stream));
+
+        Ok(Response::new(Box::pin(UnboundedReceiverStream::new(rx))))
+    }
+
+    type RegisterCmdStream = BoxStream<'static, Result<CmdInvocation, Status>>;
+
+    async fn register_cmd(
+        &self,
+        request: Request<CmdDef>,
+    ) -> Result<Response<Self::RegisterCmdStream>, Status> {
+        self.record(&request);
+
+        let def = request.into_inner();
+        let (invocations, rx) = mpsc::unbounded_channel();
+
+        self.shared.update(|state| {
+            let command = Command {
+                def: def.clone(),
+                invocations,
+            };
+
+            state.commands.insert(def.name, command);
+        });
+
+        Ok(Response::new(Box::pin(UnboundedReceiverStream::new(rx))))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<Message>,
+    ) -> Result<Response<MessageRes>, Status> {
+        self.record(&request);
+
+        let delay = self.shared.lock().delay;
+        tokio::time::sleep(delay).await;
+
+        let failure = self.shared.lock().failures.pop_front();
+
+        if let Some(status) = failure {
+            return Err(status);
+        }
+
+        let msg = request.into_inner();
+        self.shared.update(|state| state.messages.push(msg));
+
+        Ok(Response::new(MessageRes {}))
+    }
+}
@@ -0,0 +1,1588 @@
+use devzat_rs::{
+    testing::MockServer, Arity, Backoff, ClientBuilder, ClientError, CmdInvocation, CmdReply,
+    CommandRouter, Event, Listener, MessageAck, Shutdown,
+};
+use futures_util::future::join_all;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tonic::{Code, Status};
+
+fn invocation(args: &str) -> CmdInvocation {
+    CmdInvocation {
+        room: String::from("#main"),
+        from: String::from("Ada"),
+        args: String::from(args),
+    }
+}
+
+fn event(msg: &str) -> Event {
+    Event {
+        room: String::from("#main"),
+        from: String::from("Ada"),
+        msg: String::from(msg),
+    }
+}
+
+#[tokio::test]
+async fn cmd_replies_in_the_room_it_was_invoked_in() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd("greet", "Greet someone.", "<name>", |event| async move {
+                format!("Hello {}!", event.args)
+            })
+            .await
+    });
+
+    let def = server.wait_for_command("greet").await;
+    assert_eq!(def.info, "Greet someone.");
+    assert_eq!(def.args_info, "<name>");
+
+    server.invoke("greet", invocation("Grace")).await;
+
+    let sent = server.wait_for_messages(1).await;
+    assert_eq!(sent[0].room, "#main");
+    assert_eq!(sent[0].msg, "Hello Grace!");
+}
+
+#[tokio::test]
+async fn borrowing_cmd_replies_in_the_room_it_was_invoked_in() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_ref("greet", "Greet someone.", "<name>", |event| {
+                let reply = format!("Hello {}!", event.args);
+                async move { reply }
+            })
+            .await
+    });
+
+    server.wait_for_command("greet").await;
+    server.invoke("greet", invocation("Grace")).await;
+
+    let sent = server.wait_for_messages(1).await;
+    assert_eq!(sent[0].room, "#main");
+    assert_eq!(sent[0].msg, "Hello Grace!");
+}
+
+#[tokio::test]
+async fn listener_receives_matching_events() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let listener = Listener::builder().regex("^hi").build();
+
+        client
+            .register_listener(listener, move |event| {
+                let _ = tx.send(event.msg);
+                async { None }
+            })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    assert_eq!(server.emit(event("bye")).await, "bye");
+    assert_eq!(server.emit(event("hi there")).await, "hi there");
+
+    assert_eq!(rx.recv().await.as_deref(), Some("hi there"));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn middleware_response_replaces_the_message() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        let listener = Listener::builder().middleware(true).build();
+
+        client
+            .register_middleware(listener, |event| Some(event.msg.to_uppercase()))
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    assert_eq!(server.emit(event("quiet")).await, "QUIET");
+    assert_eq!(server.emit(event("still")).await, "STILL");
+}
+
+#[tokio::test]
+async fn once_middleware_response_is_sent_before_closing() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let registration = tokio::spawn(async move {
+        let listener = Listener::builder().middleware(true).once(true).build();
+
+        client
+            .register_middleware(listener, |event| Some(format!("{}!", event.msg)))
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    assert_eq!(server.emit(event("once")).await, "once!");
+
+    registration.await.unwrap().unwrap();
+    assert_eq!(server.responses(), [Some(String::from("once!"))]);
+}
+
+#[tokio::test]
+async fn wait_until_ready_waits_for_the_server() {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let client = ClientBuilder::new()
+        .host(format!("http://{}", addr))
+        .token(MockServer::TOKEN)
+        .connect_lazy(true)
+        .build()
+        .await
+        .unwrap();
+
+    let server = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        MockServer::start_at(addr).await.unwrap()
+    });
+
+    client
+        .wait_until_ready(Duration::from_secs(10))
+        .await
+        .unwrap();
+
+    let server = server.await.unwrap();
+    assert!(!server.authorizations().is_empty());
+}
+
+#[tokio::test]
+async fn messages_to_a_room_keep_their_order() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    // More messages than a room queues at once, so that some of them wait to be queued.
+    let tasks = ["a", "b"].map(|task| {
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            let sends = (0..40).map(|i| {
+                let msg = format!("{}{}", task, i);
+                let client = client.clone();
+
+                async move { client.send_message("#main", None, msg, None).await }
+            });
+
+            join_all(sends).await
+        })
+    });
+
+    for task in tasks {
+        assert!(task.await.unwrap().iter().all(Result::is_ok));
+    }
+
+    let sent: Vec<_> = server.messages().into_iter().map(|msg| msg.msg).collect();
+
+    for task in ["a", "b"] {
+        let order: Vec<_> = sent.iter().filter(|msg| msg.starts_with(task)).collect();
+        let expected: Vec<_> = (0..40).map(|i| format!("{}{}", task, i)).collect();
+
+        assert_eq!(order, expected.iter().collect::<Vec<_>>());
+    }
+}
+
+#[tokio::test]
+async fn messages_are_sent_after_the_room_queue_emptied() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    for msg in ["first", "second"] {
+        client
+            .send_message("#main", None, String::from(msg), None)
+            .await
+            .unwrap();
+
+        // Leaves time for the queue of the room to stop.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let sent: Vec<_> = server.messages().into_iter().map(|msg| msg.msg).collect();
+    assert_eq!(sent, ["first", "second"]);
+}
+
+#[tokio::test]
+async fn requests_use_the_rotated_token() {
+    let server = MockServer::start().await.unwrap();
+    let mut client = server.client().await.unwrap();
+
+    client
+        .send_message("#main", None, String::from("before"), None)
+        .await
+        .unwrap();
+
+    client.set_token(String::from("dvz.token@rotated")).unwrap();
+
+    client
+        .send_message("#main", None, String::from("after"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.authorizations(),
+        [
+            format!("Bearer {}", MockServer::TOKEN),
+            String::from("Bearer dvz.token@rotated")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn router_fallback_answers_unparsable_arguments() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        CommandRouter::new()
+            .add("greet", "Greet someone.", "<name>", |event| async move {
+                format!("Hello {}!", event.args)
+            })
+            .fallback(|_, err| async move { format!("Oops: {}", err) })
+            .run(&client)
+            .await
+    });
+
+    server.invoke("greet", invocation("\"Grace")).await;
+    server.invoke("greet", invocation("Grace")).await;
+
+    let sent: Vec<_> = server
+        .wait_for_messages(2)
+        .await
+        .into_iter()
+        .map(|msg| msg.msg)
+        .collect();
+
+    assert_eq!(sent, ["Oops: unterminated \" quote", "Hello Grace!"]);
+}
+
+#[tokio::test]
+async fn router_help_command_replies_with_the_help() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let router = CommandRouter::new()
+        .add("ping", "Check the bot is alive.", "", |_| async move {
+            String::from("pong")
+        })
+        .add("greet", "Greet someone.", "<name>", |event| async move {
+            format!("Hello {}!", event.args)
+        })
+        .with_help("help");
+    let help = router.help();
+
+    tokio::spawn(async move { router.run(&client).await });
+
+    for name in ["ping", "greet", "help"] {
+        server.wait_for_command(name).await;
+    }
+
+    server.invoke("help", invocation("")).await;
+
+    let sent = server.wait_for_messages(1).await;
+    assert_eq!(sent[0].msg, help);
+    assert_eq!(
+        sent[0].msg,
+        "greet <name> - Greet someone.\nping - Check the bot is alive."
+    );
+}
+
+#[tokio::test]
+async fn send_to_default_uses_the_default_room() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.builder().default_room("bots").build().await.unwrap();
+
+    client.send_to_default("Hello!").await.unwrap();
+
+    let sent = server.messages();
+    assert_eq!(sent[0].room, "#bots");
+    assert_eq!(sent[0].msg, "Hello!");
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn only_messages_over_the_gzip_threshold_are_compressed() {
+    let server = MockServer::start().await.unwrap();
+    let msg = devzat_rs::Message::to("#main")
+        .text("x".repeat(100))
+        .build();
+    let threshold = prost::Message::encoded_len(&msg);
+
+    let client = server
+        .builder()
+        .gzip(true)
+        .gzip_threshold(threshold)
+        .build()
+        .await
+        .unwrap();
+
+    client.send(msg.clone()).await.unwrap();
+
+    let mut larger = msg;
+    larger.msg.push('x');
+    client.send(larger).await.unwrap();
+
+    assert_eq!(server.encodings(), [None, Some(String::from("gzip"))]);
+}
+
+#[tokio::test]
+async fn middleware_responses_are_sent_for_every_event() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        let listener = Listener::builder().middleware(true).build();
+
+        client
+            .register_listener(listener, |event| async move {
+                event
+                    .msg
+                    .contains("heck")
+                    .then(|| event.msg.replace("heck", "h*ck"))
+            })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    assert_eq!(server.emit(event("what the heck")).await, "what the h*ck");
+    assert_eq!(server.emit(event("hello")).await, "hello");
+
+    assert_eq!(
+        server.responses(),
+        [Some(String::from("what the h*ck")), None]
+    );
+}
+
+#[tokio::test]
+async fn clones_send_from_several_tasks_on_the_same_connection() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let tasks = ["#main", "#general"].map(|room| {
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            client
+                .send_message(room, None, String::from("Hello!"), None)
+                .await
+        })
+    });
+
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    let mut rooms: Vec<_> = server.messages().into_iter().map(|msg| msg.room).collect();
+    rooms.sort();
+    assert_eq!(rooms, ["#general", "#main"]);
+
+    let peers = server.peers();
+    assert_eq!(peers.len(), 2);
+    assert!(peers[0].is_some());
+    assert_eq!(peers[0], peers[1]);
+}
+
+#[tokio::test]
+async fn send_message_retry_retries_unavailable_statuses() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    server.fail_next_message(Status::unavailable("restarting"));
+    server.fail_next_message(Status::unavailable("restarting"));
+
+    let msg = devzat_rs::Message::to("#main").text("Hello!").build();
+    client
+        .send_message_retry(msg, 3, Duration::from_millis(10))
+        .await
+        .unwrap();
+
+    assert_eq!(server.authorizations().len(), 3);
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test]
+async fn send_message_retry_returns_other_statuses() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    server.fail_next_message(Status::unknown("devzat failed"));
+
+    let msg = devzat_rs::Message::to("#main").text("Hello!").build();
+    let err = client
+        .send_message_retry(msg, 3, Duration::from_millis(10))
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.status().map(Status::code), Some(Code::Unknown));
+    assert_eq!(server.authorizations().len(), 1);
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test]
+async fn cancelled_registrations_return_while_waiting_for_events() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let token = CancellationToken::new();
+
+    let cmd = tokio::spawn({
+        let client = client.clone();
+        let token = token.clone();
+
+        async move {
+            client
+                .register_cmd_cancellable("greet", "", "", token, |_| async move {
+                    String::from("Hello!")
+                })
+                .await
+        }
+    });
+
+    let listener = tokio::spawn({
+        let token = token.clone();
+
+        async move {
+            client
+                .register_listener_cancellable(Listener::all(), token, |_| async { None })
+                .await
+        }
+    });
+
+    server.wait_for_command("greet").await;
+    server.wait_for_listeners(1).await;
+    token.cancel();
+
+    assert_eq!(cmd.await.unwrap().unwrap(), 0);
+    assert_eq!(listener.await.unwrap().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn resilient_registrations_register_again_after_the_stream_failed() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+
+    tokio::spawn({
+        let client = client.clone();
+
+        async move {
+            client
+                .register_cmd_resilient("greet", "", "", backoff, |event| async move {
+                    format!("Hello {}!", event.args)
+                })
+                .await
+        }
+    });
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        client
+            .register_listener_resilient(Listener::all(), backoff, move |event| {
+                let _ = tx.send(event.msg);
+                async { None }
+            })
+            .await
+    });
+
+    server.wait_for_command("greet").await;
+    server.wait_for_listeners(1).await;
+
+    server.fail_command("greet", Status::unavailable("restarting"));
+    server.fail_listeners(Status::unavailable("restarting"));
+
+    server.invoke("greet", invocation("Grace")).await;
+    assert_eq!(server.wait_for_messages(1).await[0].msg, "Hello Grace!");
+
+    server.wait_for_listeners(1).await;
+    server.emit(event("hi")).await;
+    assert_eq!(rx.recv().await.as_deref(), Some("hi"));
+}
+
+#[tokio::test]
+async fn resilient_registrations_return_errors_that_are_not_transient() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+
+    let registration = tokio::spawn(async move {
+        client
+            .register_cmd_resilient("greet", "", "", backoff, |_| async move {
+                String::from("Hello!")
+            })
+            .await
+    });
+
+    server.wait_for_command("greet").await;
+    server.fail_command("greet", Status::unauthenticated("invalid token"));
+
+    let err = registration.await.unwrap().unwrap_err();
+    assert_eq!(err.status().map(Status::code), Some(Code::Unauthenticated));
+}
+
+#[tokio::test]
+async fn once_listener_is_only_called_for_the_first_event() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let registration = tokio::spawn(async move {
+        let listener = Listener::builder().once(true).build();
+
+        client
+            .register_listener(listener, move |event| {
+                let _ = tx.send(event.msg);
+                async { None }
+            })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    server.emit(event("first")).await;
+    server.emit(event("second")).await;
+
+    registration.await.unwrap().unwrap();
+
+    assert_eq!(rx.recv().await.as_deref(), Some("first"));
+    assert_eq!(rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn listener_returning_a_message_without_being_a_middleware_fails() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let registration = tokio::spawn(async move {
+        client
+            .register_listener(Listener::all(), |event| async move { Some(event.msg) })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    server.emit(event("hi")).await;
+
+    let err = registration.await.unwrap().unwrap_err();
+    assert!(matches!(err, ClientError::MiddlewareMisuse));
+}
+
+#[tokio::test]
+async fn registrations_return_ok_when_devzat_closes_the_stream() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let cmd = tokio::spawn({
+        let client = client.clone();
+
+        async move {
+            client
+                .register_cmd("greet", "", "", |_| async move { String::from("Hello!") })
+                .await
+        }
+    });
+
+    let listener = tokio::spawn(async move {
+        client
+            .register_listener(Listener::all(), |_| async { None })
+            .await
+    });
+
+    server.wait_for_command("greet").await;
+    server.wait_for_listeners(1).await;
+
+    server.close_command("greet");
+    server.close_listeners();
+
+    cmd.await.unwrap().unwrap();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn registrations_return_a_transient_error_when_the_stream_fails() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let cmd = tokio::spawn({
+        let client = client.clone();
+
+        async move {
+            client
+                .register_cmd("greet", "", "", |_| async move { String::from("Hello!") })
+                .await
+        }
+    });
+
+    let listener = tokio::spawn(async move {
+        client
+            .register_listener(Listener::all(), |_| async { None })
+            .await
+    });
+
+    server.wait_for_command("greet").await;
+    server.wait_for_listeners(1).await;
+
+    server.fail_command("greet", Status::unavailable("connection lost"));
+    server.fail_listeners(Status::unavailable("connection lost"));
+
+    assert!(cmd.await.unwrap().unwrap_err().is_transient());
+    assert!(listener.await.unwrap().unwrap_err().is_transient());
+}
+
+#[tokio::test]
+async fn callbacks_running_past_the_timeout_get_a_timeout_reply() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .callback_timeout(Duration::from_millis(50))
+        .build()
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd("fetch", "", "", |event| async move {
+                if event.args == "slow" {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+
+                String::from("Fetched.")
+            })
+            .await
+    });
+
+    server.invoke("fetch", invocation("slow")).await;
+    server.invoke("fetch", invocation("fast")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert_eq!(sent[0].msg, "This command timed out.");
+    assert_eq!(sent[0].ephemeral_to.as_deref(), Some("Ada"));
+    assert_eq!(sent[1].msg, "Fetched.");
+    assert_eq!(sent[1].ephemeral_to, None);
+}
+
+#[tokio::test]
+async fn requests_use_the_auth_scheme() {
+    let server = MockServer::start().await.unwrap();
+
+    for scheme in ["Token", ""] {
+        let client = server.builder().auth_scheme(scheme).build().await.unwrap();
+        client
+            .send_message("#main", None, String::from("Hello!"), None)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        server.headers("authorization"),
+        [
+            Some(format!("Token {}", MockServer::TOKEN)),
+            Some(String::from(MockServer::TOKEN))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn requests_send_the_token_and_the_metadata() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .metadata("x-gateway-key", "s3cret")
+        .build()
+        .await
+        .unwrap();
+
+    client
+        .send_message("#main", None, String::from("Hello!"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.headers("authorization"),
+        [Some(format!("Bearer {}", MockServer::TOKEN))]
+    );
+    assert_eq!(
+        server.headers("x-gateway-key"),
+        [Some(String::from("s3cret"))]
+    );
+}
+
+#[tokio::test]
+async fn requests_identify_the_plugin_in_the_user_agent() {
+    let server = MockServer::start().await.unwrap();
+
+    for builder in [server.builder(), server.builder().user_agent("my-bot/1.0")] {
+        let client = builder.build().await.unwrap();
+
+        client
+            .send_message("#main", None, String::from("Hello!"), None)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(
+        server.headers("user-agent"),
+        [
+            Some(format!(
+                "devzat-rs/{} tonic/0.7.2",
+                env!("CARGO_PKG_VERSION")
+            )),
+            Some(String::from("my-bot/1.0 tonic/0.7.2"))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rate_limit_spreads_messages_over_time() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.builder().rate_limit(2).build().await.unwrap();
+
+    tokio::time::pause();
+    let started = tokio::time::Instant::now();
+
+    let sends = (0..10).map(|i| client.send_message("#main", None, i.to_string(), None));
+    assert!(join_all(sends).await.iter().all(Result::is_ok));
+
+    // Two messages right away, then one every 500ms.
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(4), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(4500), "{:?}", elapsed);
+    assert_eq!(server.messages().len(), 10);
+}
+
+#[tokio::test]
+async fn invocations_are_not_read_while_every_callback_permit_is_taken() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .max_concurrent_callbacks(1)
+        .max_decoding_message_size(100)
+        .build()
+        .await
+        .unwrap();
+    let gate = Arc::new(Semaphore::new(0));
+
+    let mut registration = tokio::spawn({
+        let gate = gate.clone();
+
+        async move {
+            client
+                .register_cmd_concurrent("work", "", "", move |_| {
+                    let gate = gate.clone();
+
+                    async move {
+                        let _ = gate.acquire().await;
+                        String::from("Done.")
+                    }
+                })
+                .await
+        }
+    });
+
+    // The second invocation is read and waits for the permit, which keeps the third unread.
+    server.invoke("work", invocation("first")).await;
+    server.invoke("work", invocation("second")).await;
+    server.invoke("work", invocation(&"x".repeat(200))).await;
+
+    // The oversized invocation would end the registration as soon as it's read.
+    let pending = tokio::time::timeout(Duration::from_millis(200), &mut registration).await;
+    assert!(pending.is_err());
+
+    gate.add_permits(1);
+
+    let err = registration.await.unwrap().unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::MessageTooLarge { limit: 100, .. }
+    ));
+    assert_eq!(server.messages()[0].msg, "Done.");
+}
+
+#[tokio::test]
+async fn idle_commands_do_not_hold_a_callback_permit() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .max_concurrent_callbacks(1)
+        .build()
+        .await
+        .unwrap();
+
+    for name in ["one", "two"] {
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            client
+                .register_cmd_concurrent(
+                    name,
+                    "",
+                    "",
+                    move |_| async move { format!("Ran {}.", name) },
+                )
+                .await
+        });
+    }
+
+    server.wait_for_command("two").await;
+    server.invoke("one", invocation("")).await;
+    server.invoke("one", invocation("")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert!(sent.iter().all(|msg| msg.msg == "Ran one."));
+}
+
+#[tokio::test]
+async fn restricted_cmd_only_runs_for_allowed_users() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let mods = HashSet::from([String::from("Ada")]);
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_restricted("mute", "", "<name>", mods, |event| async move {
+                format!("{} was muted.", event.args)
+            })
+            .await
+    });
+
+    let denied = CmdInvocation {
+        from: String::from("Mallory"),
+        ..invocation("Grace")
+    };
+
+    server.invoke("mute", denied).await;
+    server.invoke("mute", invocation("Grace")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert_eq!(sent[0].msg, "Permission denied.");
+    assert_eq!(sent[0].ephemeral_to.as_deref(), Some("Mallory"));
+    assert_eq!(sent[1].msg, "Grace was muted.");
+    assert_eq!(sent[1].ephemeral_to, None);
+}
+
+#[tokio::test]
+async fn messages_past_their_deadline_fail_with_deadline_exceeded() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    server.delay_messages(Duration::from_millis(500));
+
+    let err = client
+        .send_message_with_deadline(
+            "#main",
+            None,
+            String::from("The vote closes now!"),
+            None,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.status().map(Status::code), Some(Code::DeadlineExceeded));
+}
+
+#[tokio::test]
+async fn tokens_that_are_not_valid_headers_are_rejected() {
+    let server = MockServer::start().await.unwrap();
+
+    let result = server
+        .builder()
+        .token("dvz.token@hello.world1234\n")
+        .build()
+        .await;
+
+    assert!(matches!(result, Err(ClientError::InvalidToken)));
+    assert!(server.authorizations().is_empty());
+}
+
+#[tokio::test]
+async fn cmd_replies_come_from_the_given_sender() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    for (name, from) in [("greet", None), ("greet-as", Some(String::from("Rusty")))] {
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            client
+                .register_cmd_as(
+                    name,
+                    "",
+                    "",
+                    from,
+                    |_| async move { String::from("Hello!") },
+                )
+                .await
+        });
+    }
+
+    server.invoke("greet", invocation("")).await;
+    server.wait_for_messages(1).await;
+    server.invoke("greet-as", invocation("")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert_eq!(sent[0].from, None);
+    assert_eq!(sent[1].from.as_deref(), Some("Rusty"));
+}
+
+#[tokio::test]
+async fn plaintext_hosts_connect_without_tls() {
+    let server = MockServer::start().await.unwrap();
+    assert!(server.host().starts_with("http://"));
+
+    let client = ClientBuilder::new()
+        .host(server.host())
+        .token(MockServer::TOKEN)
+        .build()
+        .await
+        .unwrap();
+
+    client
+        .send_message("#main", None, String::from("Hello!"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(server.messages()[0].msg, "Hello!");
+}
+
+#[cfg(not(feature = "tls"))]
+#[tokio::test]
+async fn https_hosts_need_a_tls_feature() {
+    let result = ClientBuilder::new()
+        .host("https://devzat.hackclub.com:5556")
+        .token(MockServer::TOKEN)
+        .build()
+        .await;
+
+    assert!(matches!(result, Err(ClientError::TlsNotEnabled)));
+}
+
+#[tokio::test]
+async fn messages_over_the_encoding_limit_are_not_sent() {
+    let server = MockServer::start().await.unwrap();
+    let msg = devzat_rs::Message::to("#main")
+        .text("x".repeat(100))
+        .build();
+    let limit = prost::Message::encoded_len(&msg);
+
+    let client = server
+        .builder()
+        .max_encoding_message_size(limit)
+        .build()
+        .await
+        .unwrap();
+
+    client.send(msg.clone()).await.unwrap();
+
+    let mut larger = msg;
+    larger.msg.push('x');
+    let err = client.send(larger).await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        ClientError::MessageTooLarge { size, limit: max } if size == limit + 1 && max == limit
+    ));
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_listener_regexes_are_rejected_before_registering() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let listener = Listener::builder().regex("(").build();
+    let err = client
+        .register_listener(listener, |_| async { None })
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, ClientError::InvalidRegex(_)));
+    assert!(server.authorizations().is_empty());
+}
+
+#[tokio::test]
+async fn listeners_skip_events_from_the_bot_itself() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .bot_username("Rusty")
+        .build()
+        .await
+        .unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        client
+            .register_listener(Listener::all(), move |event| {
+                let _ = tx.send(event.from);
+                async { None }
+            })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+
+    let own = Event {
+        from: String::from("Rusty"),
+        ..event("echo")
+    };
+
+    server.emit(own).await;
+    server.emit(event("echo")).await;
+
+    assert_eq!(rx.recv().await.as_deref(), Some("Ada"));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn cmd_with_cooldown_rejects_rapid_invocations_from_the_same_user() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_with_cooldown(
+                "roll",
+                "",
+                "",
+                Duration::from_secs(60),
+                |event| async move { format!("{} rolled a 4.", event.from) },
+            )
+            .await
+    });
+
+    let other = CmdInvocation {
+        from: String::from("Grace"),
+        ..invocation("")
+    };
+
+    server.invoke("roll", invocation("")).await;
+    server.invoke("roll", invocation("")).await;
+    server.invoke("roll", other).await;
+
+    let sent = server.wait_for_messages(3).await;
+    assert_eq!(sent[0].msg, "Ada rolled a 4.");
+    assert!(sent[1].msg.starts_with("Please wait"), "{}", sent[1].msg);
+    assert_eq!(sent[1].ephemeral_to.as_deref(), Some("Ada"));
+    assert_eq!(sent[2].msg, "Grace rolled a 4.");
+}
+
+#[tokio::test]
+async fn fallible_cmd_errors_get_a_generic_reply() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_fallible("parse", "", "<number>", |event| async move {
+                event
+                    .args
+                    .parse::<u32>()
+                    .map(|number| format!("Got {}.", number))
+            })
+            .await
+    });
+
+    server.invoke("parse", invocation("four")).await;
+    server.invoke("parse", invocation("4")).await;
+
+    let sent: Vec<_> = server
+        .wait_for_messages(2)
+        .await
+        .into_iter()
+        .map(|msg| msg.msg)
+        .collect();
+
+    assert_eq!(
+        sent,
+        ["Something went wrong while running this command.", "Got 4."]
+    );
+}
+
+#[tokio::test]
+async fn debounced_listener_is_called_once_for_a_burst() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        client
+            .register_listener_debounced(
+                Listener::all(),
+                Duration::from_millis(100),
+                move |event| {
+                    let _ = tx.send((event.from, event.msg));
+                    async {}
+                },
+            )
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+
+    for msg in ["t", "ty", "typo"] {
+        server.emit(event(msg)).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let other = Event {
+        from: String::from("Grace"),
+        ..event("hi")
+    };
+    server.emit(other).await;
+
+    let mut calls = vec![rx.recv().await.unwrap(), rx.recv().await.unwrap()];
+    calls.sort();
+
+    assert_eq!(
+        calls,
+        [
+            (String::from("Ada"), String::from("typo")),
+            (String::from("Grace"), String::from("hi"))
+        ]
+    );
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn stopping_one_command_leaves_the_others_running() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let router = CommandRouter::new()
+        .add("vote", "", "<map>", |event| async move {
+            format!("Voted for {}.", event.args)
+        })
+        .add("ping", "", "", |_| async move { String::from("pong") });
+    let vote = router.registration("vote").unwrap();
+    let ping = router.registration("ping").unwrap();
+
+    let running = tokio::spawn(async move { router.run(&client).await });
+
+    server.wait_for_command("vote").await;
+    server.wait_for_command("ping").await;
+
+    vote.stop();
+    vote.stop();
+
+    server.invoke("ping", invocation("")).await;
+    assert_eq!(server.wait_for_messages(1).await[0].msg, "pong");
+    assert!(!running.is_finished());
+
+    // The router only returns once every command is done, so `vote` stopped too.
+    ping.stop();
+    running.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn system_messages_have_no_sender() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    client
+        .send_system_message("main", String::from("Restarting in 5 minutes."))
+        .await
+        .unwrap();
+
+    let sent = server.messages();
+    assert_eq!(sent[0].room, "#main");
+    assert_eq!(sent[0].from, None);
+    assert_eq!(sent[0].msg, "Restarting in 5 minutes.");
+}
+
+#[tokio::test]
+async fn graceful_shutdown_waits_for_running_callbacks() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let shutdown = Shutdown::new(Duration::from_secs(5));
+    let (started, mut running) = mpsc::unbounded_channel();
+
+    let registration = tokio::spawn({
+        let shutdown = shutdown.clone();
+
+        async move {
+            client
+                .register_cmd_concurrent_graceful("slow", "", "", shutdown, move |_| {
+                    let _ = started.send(());
+
+                    async {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        String::from("Done!")
+                    }
+                })
+                .await
+        }
+    });
+
+    server.invoke("slow", invocation("")).await;
+    running.recv().await.unwrap();
+    shutdown.trigger();
+
+    registration.await.unwrap().unwrap();
+    assert_eq!(server.messages()[0].msg, "Done!");
+}
+
+#[tokio::test]
+async fn reply_decorator_wraps_cmd_replies() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .reply_decorator(|reply| format!("[bot] {}", reply))
+        .build()
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd("echo", "", "<text>", |event| async move { event.args })
+            .await
+    });
+
+    server.invoke("echo", invocation("")).await;
+    server.invoke("echo", invocation("pong")).await;
+
+    let sent = server.wait_for_messages(1).await;
+    assert_eq!(sent[0].msg, "[bot] pong");
+}
+
+#[tokio::test]
+async fn blank_cmd_replies_are_not_sent() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd("echo", "", "<text>", |event| async move { event.args })
+            .await
+    });
+
+    for args in ["", " \n\t", "pong"] {
+        server.invoke("echo", invocation(args)).await;
+    }
+
+    // Replies are sent in order, so any blank one would come first.
+    let sent = server.wait_for_messages(1).await;
+    assert_eq!(sent[0].msg, "pong");
+    assert_eq!(sent.len(), 1);
+}
+
+// The only test reading the `DEVZAT_*` variables, so that setting them can't affect the others.
+#[tokio::test]
+async fn clients_are_built_from_the_environment() {
+    let server = MockServer::start().await.unwrap();
+
+    std::env::remove_var("DEVZAT_HOST");
+    std::env::remove_var("DEVZAT_TOKEN");
+
+    let missing = match ClientBuilder::from_env() {
+        Err(ClientError::MissingEnv(missing)) => missing,
+        _ => panic!("expected the missing variables"),
+    };
+    assert_eq!(missing, ["DEVZAT_HOST", "DEVZAT_TOKEN"]);
+
+    std::env::set_var("DEVZAT_HOST", server.addr().to_string());
+    std::env::set_var("DEVZAT_TOKEN", MockServer::TOKEN);
+    std::env::set_var("DEVZAT_TLS", "false");
+    std::env::set_var("DEVZAT_TIMEOUT", "5");
+
+    let client = ClientBuilder::from_env().unwrap().build().await.unwrap();
+
+    client
+        .send_message("#main", None, String::from("Hello!"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.authorizations(),
+        [format!("Bearer {}", MockServer::TOKEN)]
+    );
+}
+
+#[tokio::test]
+async fn checked_cmd_answers_the_wrong_number_of_arguments_with_its_usage() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_checked("swap", "", "<a> <b>", Arity::Exact(2), |event| async move {
+                let args = event.parsed_args().unwrap();
+                format!("{} {}", args[1], args[0])
+            })
+            .await
+    });
+
+    for args in ["one", "one two three", "one two"] {
+        server.invoke("swap", invocation(args)).await;
+    }
+
+    let sent = server.wait_for_messages(3).await;
+
+    for usage in &sent[..2] {
+        assert_eq!(usage.msg, "Usage: swap <a> <b>");
+        assert_eq!(usage.ephemeral_to.as_deref(), Some("Ada"));
+    }
+
+    assert_eq!(sent[2].msg, "two one");
+}
+
+#[tokio::test]
+async fn actions_send_no_message() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        client
+            .register_action("log", "", "<text>", move |event| {
+                let _ = tx.send(event.args);
+                async {}
+            })
+            .await
+    });
+
+    server.invoke("log", invocation("first")).await;
+    server.invoke("log", invocation("second")).await;
+
+    assert_eq!(rx.recv().await.as_deref(), Some("first"));
+    assert_eq!(rx.recv().await.as_deref(), Some("second"));
+
+    // Leaves time for a reply to the invocations to be sent.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test]
+async fn send_message_ack_returns_the_acknowledgement() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let msg = devzat_rs::Message::to("#main").text("Hello!").build();
+    let ack = client.send_message_ack(msg.clone()).await.unwrap();
+
+    assert_eq!(ack, MessageAck::default());
+    assert_eq!(server.messages()[0], msg);
+
+    server.fail_next_message(Status::permission_denied("muted"));
+    let err = client.send_message_ack(msg).await.unwrap_err();
+    assert_eq!(err.status().map(Status::code), Some(Code::PermissionDenied));
+}
+
+#[tokio::test]
+async fn mention_invoker_prefixes_replies_with_the_invoker() {
+    let server = MockServer::start().await.unwrap();
+    let client = server
+        .builder()
+        .mention_invoker(true)
+        .build()
+        .await
+        .unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_reply("echo", "", "<text>", |event| async move {
+                match event.args.as_str() {
+                    "secret" => CmdReply::new("Only for you.").ephemeral(),
+                    args => CmdReply::new(args),
+                }
+            })
+            .await
+    });
+
+    for args in ["here's your result", "@Ada already mentioned", "secret"] {
+        server.invoke("echo", invocation(args)).await;
+    }
+
+    let sent: Vec<_> = server
+        .wait_for_messages(3)
+        .await
+        .into_iter()
+        .map(|msg| msg.msg)
+        .collect();
+
+    assert_eq!(
+        sent,
+        [
+            "@Ada here's your result",
+            "@Ada already mentioned",
+            "Only for you."
+        ]
+    );
+}
+
+#[tokio::test]
+async fn messages_waiting_in_the_room_queue_past_their_deadline_fail_right_away() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    server.delay_messages(Duration::from_secs(2));
+
+    let earlier = tokio::spawn({
+        let client = client.clone();
+
+        async move {
+            client
+                .send_message("#main", None, String::from("Slow."), None)
+                .await
+        }
+    });
+
+    // Leaves time for the earlier message to reach the server.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = std::time::Instant::now();
+    let err = client
+        .send_message_with_deadline(
+            "#main",
+            None,
+            String::from("The vote closes now!"),
+            None,
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.status().map(Status::code), Some(Code::DeadlineExceeded));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    earlier.await.unwrap().unwrap();
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test]
+async fn broadcast_sends_one_message_per_room() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    let rooms = vec!["#main".into(), "general".into(), "#mods".into()];
+    let results = client
+        .broadcast(rooms, None, String::from("Maintenance in 10 minutes."))
+        .await;
+
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(Result::is_ok));
+
+    let sent = server.messages();
+    let rooms: Vec<_> = sent.iter().map(|msg| msg.room.as_str()).collect();
+    assert_eq!(rooms, ["#main", "#general", "#mods"]);
+    assert!(sent
+        .iter()
+        .all(|msg| msg.msg == "Maintenance in 10 minutes."));
+}
+
+#[tokio::test]
+async fn router_layer_blocks_a_user_before_the_handler() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        CommandRouter::new()
+            .add("greet", "", "<name>", move |event| {
+                let _ = tx.send(event.from);
+                async move { format!("Hello {}!", event.args) }
+            })
+            .layer(|event, next| async move {
+                match event.from.as_str() {
+                    "Mallory" => CmdReply::new("You're blocked.").ephemeral(),
+                    _ => next.run(event).await,
+                }
+            })
+            .run(&client)
+            .await
+    });
+
+    let blocked = CmdInvocation {
+        from: String::from("Mallory"),
+        ..invocation("Grace")
+    };
+
+    server.invoke("greet", blocked).await;
+    server.invoke("greet", invocation("Grace")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert_eq!(sent[0].msg, "You're blocked.");
+    assert_eq!(sent[0].ephemeral_to.as_deref(), Some("Mallory"));
+    assert_eq!(sent[1].msg, "Hello Grace!");
+
+    // Only the user who isn't blocked reached the handler.
+    assert_eq!(rx.recv().await.as_deref(), Some("Ada"));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn cmd_replies_can_be_redirected_or_ephemeral() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+
+    tokio::spawn(async move {
+        client
+            .register_cmd_reply("kick", "", "<name>", |event| async move {
+                match event.args.as_str() {
+                    "" => CmdReply::new("Usage: kick <name>").ephemeral(),
+                    name => CmdReply::new(format!("{} was kicked.", name)).in_room("#mod-log"),
+                }
+            })
+            .await
+    });
+
+    server.invoke("kick", invocation("Mallory")).await;
+    server.invoke("kick", invocation("")).await;
+
+    let sent = server.wait_for_messages(2).await;
+    assert_eq!(sent[0].room, "#mod-log");
+    assert_eq!(sent[0].msg, "Mallory was kicked.");
+    assert_eq!(sent[0].ephemeral_to, None);
+
+    assert_eq!(sent[1].room, "#main");
+    assert_eq!(sent[1].ephemeral_to.as_deref(), Some("Ada"));
+}
+
+#[tokio::test]
+async fn captures_listener_receives_the_captured_groups() {
+    let server = MockServer::start().await.unwrap();
+    let client = server.client().await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let listener = Listener::builder().regex(r"#(\d+)(?: \((\w+)\))?").build();
+
+        client
+            .register_listener_captures(listener, move |event, groups| {
+                let _ = tx.send((event.msg, groups));
+                async { None }
+            })
+            .await
+    });
+
+    server.wait_for_listeners(1).await;
+    server.emit(event("see #42 (urgent) please")).await;
+    server.emit(event("and #7")).await;
+
+    let owned = |group: &str| Some(String::from(group));
+
+    assert_eq!(
+        rx.recv().await.unwrap(),
+        (
+            String::from("see #42 (urgent) please"),
+            vec![owned("#42 (urgent)"), owned("42"), owned("urgent")]
+        )
+    );
+    assert_eq!(
+        rx.recv().await.unwrap(),
+        (String::from("and #7"), vec![owned("#7"), owned("7"), None])
+    );
+}
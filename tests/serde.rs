@@ -0,0 +1,25 @@
+use devzat_rs::{Event, Message};
+
+#[test]
+fn events_round_trip_through_json() {
+    let event = Event {
+        room: String::from("#main"),
+        from: String::from("Ada"),
+        msg: String::from("hi there"),
+    };
+
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+}
+
+#[test]
+fn messages_round_trip_through_json() {
+    let msg = Message::to("#main")
+        .text("Hello!")
+        .from("Rusty")
+        .ephemeral_to("Ada")
+        .build();
+
+    let json = serde_json::to_string(&msg).unwrap();
+    assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), msg);
+}